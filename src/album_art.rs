@@ -0,0 +1,264 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use crossterm::{QueueableCommand, cursor, Result, style, terminal::{Clear, ClearType}};
+use crossterm::style::{Color, style};
+use image::{DynamicImage, RgbImage, imageops::FilterType};
+use crate::graphics::Drawable;
+use crate::id3;
+
+// looked up next to the track when it has no embedded picture, in this order
+const COVER_FILES:[&str; 4] = ["cover.jpg", "folder.jpg", "cover.png", "folder.png"];
+// pixels of a cell when the terminal does not tell
+const DEFAULT_CELL_SIZE:(u16, u16) = (10, 20);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Protocol{
+    Off,
+    HalfBlocks,
+    Sixel,
+    Kitty,
+}
+impl Protocol{
+    pub fn parse(name:&str) -> Option<Protocol>{
+        match name{
+            "off" => Some(Protocol::Off),
+            "half-blocks" => Some(Protocol::HalfBlocks),
+            "sixel" => Some(Protocol::Sixel),
+            "kitty" => Some(Protocol::Kitty),
+            _ => None,
+        }
+    }
+    // asking the terminal would race the key thread for its answer, the environment has to do
+    pub fn detect() -> Protocol{
+        let var = |name:&str| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || program == "ghostty" {
+            Protocol::Kitty
+        }
+        else if term.contains("sixel") || term.starts_with("foot") || term.starts_with("mlterm") || program == "WezTerm" {
+            Protocol::Sixel
+        }
+        else {
+            Protocol::HalfBlocks
+        }
+    }
+}
+
+// the embedded picture, or a cover image from the track's directory
+fn load(path:&Path) -> Option<DynamicImage>{
+    let embedded = id3::Tag::read(path).ok().flatten().and_then(|tag| tag.picture());
+    let files:Vec<PathBuf> = path.parent()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default();
+    let covers = COVER_FILES.iter().filter_map(|name| {
+        files.iter().find(|file| file.file_name().is_some_and(|file_name| file_name.to_string_lossy().eq_ignore_ascii_case(name)))
+    });
+    // a picture that does not decode falls back to the next one
+    embedded.into_iter()
+        .chain(covers.filter_map(|cover| fs::read(cover).ok()))
+        .find_map(|bytes| image::load_from_memory(&bytes).ok())
+}
+
+#[cfg(unix)]
+fn cell_size() -> (u16, u16){
+    let mut size:libc::winsize = unsafe { std::mem::zeroed() };
+    let is_known = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    if !is_known || size.ws_col == 0 || size.ws_row == 0 || size.ws_xpixel == 0 || size.ws_ypixel == 0 {
+        return DEFAULT_CELL_SIZE
+    }
+    (size.ws_xpixel/size.ws_col, size.ws_ypixel/size.ws_row)
+}
+#[cfg(not(unix))]
+fn cell_size() -> (u16, u16){
+    DEFAULT_CELL_SIZE
+}
+
+fn base64(bytes:&[u8]) -> String{
+    const ALPHABET:&[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3)*4);
+    for chunk in bytes.chunks(3){
+        let value = chunk.iter().enumerate().fold(0u32, |value, (index, byte)| value | (*byte as u32) << (16-8*index));
+        for index in 0..4{
+            if index<=chunk.len() { text.push(ALPHABET[(value >> (18-6*index)) as usize & 63] as char) } else { text.push('=') }
+        }
+    }
+    text
+}
+
+// sixels are six pixel high columns, drawn once per colour of a 6x6x6 cube with the bits of the pixels in that colour
+fn sixel(image:&RgbImage) -> String{
+    let (width, height) = image.dimensions();
+    let level = |value:u8| (value as usize*5+127)/255;
+    let mut text = format!("\x1bPq\"1;1;{};{}", width, height);
+    for color in 0..216{
+        write!(text, "#{};2;{};{};{}", color, color/36*20, color/6%6*20, color%6*20).unwrap();
+    }
+    for band in (0..height).step_by(6){
+        let mut colors:BTreeMap<usize, Vec<u8>> = BTreeMap::new();
+        for y in band..(band+6).min(height){
+            for x in 0..width{
+                let pixel = image.get_pixel(x, y);
+                let color = level(pixel[0])*36 + level(pixel[1])*6 + level(pixel[2]);
+                colors.entry(color).or_insert_with(|| vec![0; width as usize])[x as usize] |= 1 << (y-band);
+            }
+        }
+        for (index, (color, bits)) in colors.iter().enumerate(){
+            // $ goes back to the start of the band for the next colour
+            if index>0 { text.push('$') }
+            write!(text, "#{}", color).unwrap();
+            let mut x = 0;
+            while x<bits.len(){
+                let run = bits[x..].iter().take_while(|sixel| **sixel == bits[x]).count();
+                let c = (63+bits[x]) as char;
+                if run>3 { write!(text, "!{}{}", run, c).unwrap() } else { text.extend(std::iter::repeat_n(c, run)) }
+                x += run;
+            }
+        }
+        text.push('-');
+    }
+    text.push_str("\x1b\\");
+    text
+}
+
+// the image is sent once in chunks, after that it is only placed again, q=2 keeps the terminal from answering
+fn kitty_transmit(image:&RgbImage, id:u32) -> String{
+    let data = base64(image.as_raw());
+    let chunks:Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut text = String::new();
+    for (index, chunk) in chunks.iter().enumerate(){
+        let more = if index+1<chunks.len() { 1 } else { 0 };
+        let chunk = std::str::from_utf8(chunk).unwrap();
+        if index == 0 {
+            write!(text, "\x1b_Ga=t,f=24,s={},v={},i={},q=2,m={};{}\x1b\\", image.width(), image.height(), id, more, chunk).unwrap();
+        } else {
+            write!(text, "\x1b_Gm={};{}\x1b\\", more, chunk).unwrap();
+        }
+    }
+    text
+}
+
+// the cover of the playing track in the column right of the list
+pub struct AlbumArt{
+    protocol:Protocol,
+    columns:u16,
+    rows:u16,
+    cell_size:(u16, u16),
+    track:Option<PathBuf>,
+    image:Option<RgbImage>,
+    // sixel data, or the kitty commands to send before the next placement
+    encoded:String,
+    // the sixel picture is on the screen or the kitty image in the terminal's memory
+    image_id:u32,
+    is_sent:Cell<bool>,
+}
+impl AlbumArt{
+    pub fn new(protocol:Protocol, columns:u16, rows:u16) -> AlbumArt{
+        // too small to show anything recognisable
+        let protocol = if columns<4 || rows<2 { Protocol::Off } else { protocol };
+        let cell_size = match protocol{
+            Protocol::Sixel | Protocol::Kitty => cell_size(),
+            _ => DEFAULT_CELL_SIZE,
+        };
+        AlbumArt{protocol,columns,rows,cell_size,track:None,image:None,encoded:String::new(),image_id:0,is_sent:Cell::new(false)}
+    }
+    // loads the art when the track changed
+    pub fn set_track(&mut self, path:&Path){
+        if self.protocol == Protocol::Off || self.track.as_deref() == Some(path) { return }
+        self.track = Some(path.to_path_buf());
+        // a half block is one pixel wide and two high
+        let (width, height) = match self.protocol{
+            Protocol::HalfBlocks => (self.columns as u32, self.rows as u32*2),
+            _ => (self.columns as u32*self.cell_size.0 as u32, self.rows as u32*self.cell_size.1 as u32),
+        };
+        self.image = load(path).map(|image| image.resize(width, height, FilterType::Triangle).to_rgb8());
+        self.encoded = match (self.protocol, self.image.as_ref()){
+            (Protocol::Sixel, Some(image)) => sixel(image),
+            (Protocol::Kitty, image) => {
+                // the previous cover is deleted from the terminal's memory with its id
+                let mut text = if self.image_id>0 { format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", self.image_id) } else { String::new() };
+                if let Some(image) = image {
+                    self.image_id += 1;
+                    text.push_str(&kitty_transmit(image, self.image_id));
+                }
+                text
+            }
+            _ => String::new(),
+        };
+        self.is_sent.set(false);
+    }
+    // clears the screen for the next frame. a sixel picture stays until text is written over it,
+    // so the rows next to it are cleared instead and it is only sent again for the next track
+    pub fn clear_screen(&self, stdout:&mut Stdout, x:u16, y:u16, screen_size:(u16, u16)) -> Result<()>{
+        if self.protocol != Protocol::Sixel || self.image.is_none() || !self.is_sent.get() {
+            stdout.queue(Clear(ClearType::All))?;
+            return Ok(())
+        }
+        let left = " ".repeat(x as usize);
+        for row in 0..screen_size.1{
+            stdout.queue(cursor::MoveTo(0, row))?;
+            match (y..y+self.rows).contains(&row){
+                true => stdout.queue(style::Print(&left))?,
+                false => stdout.queue(Clear(ClearType::CurrentLine))?,
+            };
+        }
+        Ok(())
+    }
+    fn draw_half_blocks(&self, stdout:&mut Stdout, image:&RgbImage, x:u16, y:u16) -> Result<()>{
+        let color = |x:u32, y:u32| {
+            let pixel = image.get_pixel(x, y);
+            Color::Rgb{r:pixel[0],g:pixel[1],b:pixel[2]}
+        };
+        for row in 0..image.height().div_ceil(2){
+            stdout.queue(cursor::MoveTo(x, y+row as u16))?;
+            for column in 0..image.width(){
+                let top = color(column, row*2);
+                // an odd height leaves the lower half of the last row empty
+                let cell = match row*2+1<image.height(){
+                    true => style("▀").with(top).on(color(column, row*2+1)),
+                    false => style("▀").with(top),
+                };
+                stdout.queue(style::PrintStyledContent(cell))?;
+            }
+        }
+        Ok(())
+    }
+}
+impl Drawable for AlbumArt{
+    fn draw(&self, stdout:&mut Stdout, x:u16, y:u16) -> Result<()>{
+        if self.protocol == Protocol::Kitty && !self.is_sent.get() {
+            stdout.queue(style::Print(&self.encoded))?;
+            self.is_sent.set(true);
+        }
+        let image = match self.image.as_ref(){
+            Some(image) => image,
+            None => return Ok(()),
+        };
+        // centred in the panel
+        let cell_width = match self.protocol{
+            Protocol::HalfBlocks => 1,
+            _ => self.cell_size.0 as u32,
+        };
+        let x = x + (self.columns as u32).saturating_sub(image.width().div_ceil(cell_width)) as u16/2;
+        match self.protocol{
+            Protocol::HalfBlocks => self.draw_half_blocks(stdout, image, x, y)?,
+            Protocol::Sixel if !self.is_sent.get() => {
+                stdout.queue(cursor::MoveTo(x, y))?.queue(style::Print(&self.encoded))?;
+                self.is_sent.set(true);
+            }
+            Protocol::Sixel => (),
+            Protocol::Kitty => {
+                stdout.queue(cursor::MoveTo(x, y))?;
+                // p=1 replaces the placement of the previous frame, C=1 leaves the cursor where it is
+                stdout.queue(style::Print(format!("\x1b_Ga=p,i={},p=1,C=1,q=2\x1b\\", self.image_id)))?;
+            }
+            Protocol::Off => (),
+        }
+        Ok(())
+    }
+}
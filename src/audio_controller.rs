@@ -13,14 +13,19 @@ use std::thread::spawn;
 use std::time::{Duration};
 use std::ops::{Deref, DerefMut};
 use crate::file_manager::FileManager;
-use std::path::{Path, PathBuf};
-use std::cell::{RefCell, Ref};
-use mp3_metadata::{MP3Metadata};
+use crate::cue::Segment;
+use std::path::PathBuf;
+use std::cell::RefCell;
 use std::borrow::BorrowMut;
+use anyhow::anyhow;
 
 
 pub struct TrackData {
     path: PathBuf,
+    // the decoded file, a cue track only plays the part between start and end
+    audio: PathBuf,
+    start:Duration,
+    end:Option<Duration>,
     decoder:Decoder<File>,
     iterator:Option<IntoIter<i16>>,
     prev_frames:Vec<Frame>,
@@ -38,18 +43,18 @@ pub struct TrackData {
 
 }
 impl TrackData {
-    pub fn new(path:PathBuf) -> TrackData {
-        let file = File::open(&path).unwrap();
-        let meta = mp3_metadata::read_from_file(&path).unwrap();
-        let mut decoder = Decoder::new(file.try_clone().unwrap());
+    pub fn new(path:PathBuf) -> Result<TrackData, anyhow::Error> {
+        let file = File::open(&path)?;
+        let meta = mp3_metadata::read_from_file(&path).map_err(|e| anyhow!("invalid mp3 ({:?})", e))?;
+        let mut decoder = Decoder::new(file);
 
         // get sample rate, number of channels and first frame data
-        let first_frame = decoder.next_frame().unwrap();
+        let first_frame = decoder.next_frame().map_err(|e| anyhow!("cannot decode audio ({:?})", e))?;
         let sample_rate = first_frame.sample_rate;
         let channels = first_frame.channels;
         let iterator = Some(first_frame.data.clone().into_iter());
 
-        let sample_duration = Duration::from_nanos(100_000_0000 / (sample_rate*channels as i32) as u64);
+        let sample_duration = Duration::from_nanos(1_000_000_000 / (sample_rate*channels as i32) as u64);
 
         let duration = meta.duration;
         let frames = meta.frames.len();
@@ -69,14 +74,47 @@ impl TrackData {
             frames,
             current_sample:None,
             is_active:false,
+            audio:path.clone(),
+            start:Duration::from_secs(0),
+            end:None,
             path
         };
 
 
         TrackData::set_timestamp(&mut packet_decoder, Duration::from_secs(0));
-        packet_decoder
+        Ok(packet_decoder)
+    }
+    // a track of a cue sheet, `path` is its virtual path
+    pub fn with_segment(path:PathBuf, segment:&Segment) -> Result<TrackData, anyhow::Error> {
+        let mut track = TrackData::new(segment.audio.clone())?;
+        track.set_segment(path, segment);
+        Ok(track)
+    }
+    // switches to another track of the same file without opening it again
+    pub fn set_segment(&mut self, path:PathBuf, segment:&Segment){
+        self.path = path;
+        self.start = segment.start;
+        self.end = segment.end;
+        self.is_active = false;
+        self.current_sample = None;
+        // the next track usually starts where the last one ended, playing on keeps it gapless
+        if !(self.start..self.start+Duration::from_millis(50)).contains(&self.timestamp) {
+            self.set_timestamp(self.start);
+        }
+    }
+    // the time within the track and its length, for cue tracks relative to where they start
+    pub fn position(&self) -> Duration{
+        self.timestamp.saturating_sub(self.start)
+    }
+    pub fn length(&self) -> Duration{
+        self.end.unwrap_or(self.duration).min(self.duration).saturating_sub(self.start)
     }
     pub fn get_sample(&mut self) -> Option<i16> {
+        // the end of a cue track ends it like the end of the file
+        if self.end.is_some_and(|end| self.timestamp>=end) {
+            self.current_sample = None;
+            return None
+        }
         let mut result = None;
         // get data from next frames
         if self.timestamp==self.max_time_stamp {
@@ -109,45 +147,64 @@ impl TrackData {
                         self.timestamp += self.sample_duration;
                         self.is_active = true;
                     },
-                    None => {
-                        self.iterator = Some(self.prev_frames[self.frame_index].data.clone().into_iter());
-                        self.frame_index+=1;
-                        result = self.get_sample();
+                    None => match self.prev_frames.get(self.frame_index) {
+                        Some(frame) => {
+                            self.iterator = Some(frame.data.clone().into_iter());
+                            self.frame_index+=1;
+                            result = self.get_sample();
+                        }
+                        // all decoded frames were played again, continue decoding
+                        None => {
+                            self.timestamp = self.max_time_stamp;
+                            result = self.get_sample();
+                        }
                     }
                 }
             }
         }
-        self.current_sample = result.clone();
+        self.current_sample = result;
         result
 
     }
     pub fn set_timestamp(&mut self, timestamp:Duration){
         self.timestamp = timestamp;
 
-        let frame_at_timestamp = ((timestamp.as_secs_f64()/self.duration.as_secs_f64()) as f64 * self.frames as f64) as usize;
+        let frame_at_timestamp = ((timestamp.as_secs_f64()/self.duration.as_secs_f64()) * self.frames as f64) as usize;
 
         if self.timestamp>=self.max_time_stamp{
             self.max_time_stamp = self.timestamp;
-            for _ in 0..frame_at_timestamp -self.frames_passed{
+            for _ in 0..frame_at_timestamp.saturating_sub(self.frames_passed){
                 if let Ok(frame) = self.decoder.next_frame(){
                     self.frames_passed+=1;
                     self.prev_frames.push(frame);
                 }
             }
         }
-        else{
-            self.frame_index = frame_at_timestamp;
-            self.iterator = Some(self.prev_frames[self.frame_index].data.clone().into_iter());
+        // frame count from the metadata can differ from what the decoder finds in broken files
+        else if let Some(frame) = self.prev_frames.get(frame_at_timestamp.min(self.prev_frames.len().saturating_sub(1))){
+            self.frame_index = frame_at_timestamp.min(self.prev_frames.len()-1);
+            self.iterator = Some(frame.data.clone().into_iter());
         }
     }
 }
 
+// per-channel levels of the last output buffer, after volume is applied
+// mono tracks report the same values for both channels
+#[derive(Clone, Copy, Default)]
+pub struct Levels {
+    pub peak:[f32;2],
+    pub rms:[f32;2],
+}
+
 pub struct AudioPlayer {
     format: Format,
     device: Device,
     volume: Arc<Mutex<f32>>,
+    levels: Arc<Mutex<Levels>>,
     current_track: Option<Arc<Mutex<TrackData>>>,
-    current_track_meta:Option<RefCell<MP3Metadata>>,
+    skipped: Vec<(PathBuf, anyhow::Error)>,
+    // counts the tracks started, a new value is a new play even when repeat-one starts the same track
+    plays_started: u64,
     event_loop:Arc<EventLoop>,
     stream_id: Arc<StreamId>,
     pub file_manager: FileManager,
@@ -155,33 +212,64 @@ pub struct AudioPlayer {
     is_playing:RefCell<bool>,
 }
 impl AudioPlayer {
-    pub fn new(dir:&Path) -> Result<AudioPlayer,()> {
-        let host = cpal::default_host();
-        let device = host.default_output_device().expect("failed to find a default output device");
-        if let Ok(file_manager) = FileManager::new(dir){
-            let current_track = TrackData::new(file_manager.get_current());
-            let mut format = device.default_output_format().unwrap();
-            format.channels = current_track.channels as u16;
-            let event_loop = Arc::new(host.event_loop());
-            let stream_id = Arc::new(event_loop.build_output_stream(&device,&format).unwrap());
-
-            Ok(AudioPlayer {
-                device,
-                format,
-                event_loop,
-                stream_id,
-                volume:Arc::new(Mutex::new(1.0)),
-                current_track:Some(Arc::new(Mutex::new(current_track))),
-                current_track_meta:Some(RefCell::new(mp3_metadata::read_from_file(file_manager.get_current()).unwrap())),
-                file_manager,
-                is_running:RefCell::new(false),
-                is_playing:RefCell::new(false),
-            })
+    // the current track of the file manager or the first one after it that opens
+    fn open(file_manager:&FileManager, path:PathBuf) -> Result<TrackData, anyhow::Error> {
+        match file_manager.segment(&path) {
+            Some(segment) => TrackData::with_segment(path.clone(), segment),
+            None => TrackData::new(path),
         }
-        else{
-            Err(())
+    }
+    fn open_playable(file_manager:&mut FileManager, skipped:&mut Vec<(PathBuf, anyhow::Error)>) -> Result<TrackData, anyhow::Error> {
+        let mut path = file_manager.get_current();
+        // bad files are only marked, so with repeat-all next() would go around forever
+        let mut attempts = file_manager.file_paths.len() + file_manager.queued().len();
+        loop {
+            match AudioPlayer::open(file_manager, path.clone()) {
+                Ok(track) => return Ok(track),
+                Err(e) => {
+                    file_manager.mark_bad(&path, &e);
+                    skipped.push((path, e));
+                }
+            }
+            attempts -= 1;
+            if attempts == 0 {
+                return Err(anyhow!("no playable mp3 files found"))
+            }
+            path = file_manager.next().ok_or_else(|| anyhow!("no playable mp3 files found"))?;
         }
+    }
+    pub fn new(mut file_manager:FileManager) -> Result<AudioPlayer, anyhow::Error> {
+        let host = cpal::default_host();
+        let device = host.default_output_device().expect("failed to find a default output device");
+        let mut skipped = Vec::new();
+        let current_track = AudioPlayer::open_playable(&mut file_manager, &mut skipped)?;
+        let mut format = device.default_output_format().unwrap();
+        format.channels = current_track.channels as u16;
+        let event_loop = Arc::new(host.event_loop());
+        let stream_id = Arc::new(event_loop.build_output_stream(&device,&format).unwrap());
 
+        Ok(AudioPlayer {
+            device,
+            format,
+            event_loop,
+            stream_id,
+            volume:Arc::new(Mutex::new(1.0)),
+            levels:Arc::new(Mutex::new(Levels::default())),
+            current_track:Some(Arc::new(Mutex::new(current_track))),
+            skipped,
+            plays_started:0,
+            file_manager,
+            is_running:RefCell::new(false),
+            is_playing:RefCell::new(false),
+        })
+    }
+    // replaces the list, e.g. with another folder, the old one stays when nothing in the new one plays
+    // returns the list that was replaced
+    pub fn load(&mut self, mut file_manager:FileManager) -> Result<FileManager, anyhow::Error> {
+        let track = AudioPlayer::open_playable(&mut file_manager, &mut self.skipped)?;
+        let previous = std::mem::replace(&mut self.file_manager, file_manager);
+        self.change_track(track);
+        Ok(previous)
     }
     pub fn run(&mut self) -> Result<(), anyhow::Error> {
         *self.is_running.borrow_mut() = true;
@@ -189,6 +277,7 @@ impl AudioPlayer {
 
         let event_loop_clone = self.event_loop.clone();
         let volume_clone = self.volume.clone();
+        let levels_clone = self.levels.clone();
         let current_track_clone = self.current_track.as_ref().unwrap().clone();
 
         spawn(move || {
@@ -200,12 +289,13 @@ impl AudioPlayer {
                         return;
                     }
                 };
-                match data {
+                let levels = match data {
                     cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::F32(mut buffer) } => { AudioPlayer::write_data(&mut buffer,current_track_clone.lock().unwrap().deref_mut(), *volume_clone.lock().unwrap()) },
-                    cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer) } => { AudioPlayer::write_data(&mut buffer,current_track_clone.lock().unwrap().deref_mut(), *volume_clone.lock().unwrap()); },
-                    cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer) } => { AudioPlayer::write_data(&mut buffer,current_track_clone.lock().unwrap().deref_mut(), *volume_clone.lock().unwrap()); },
-                    _ => ()
-                }
+                    cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::U16(mut buffer) } => { AudioPlayer::write_data(&mut buffer,current_track_clone.lock().unwrap().deref_mut(), *volume_clone.lock().unwrap()) },
+                    cpal::StreamData::Output { buffer: cpal::UnknownTypeOutputBuffer::I16(mut buffer) } => { AudioPlayer::write_data(&mut buffer,current_track_clone.lock().unwrap().deref_mut(), *volume_clone.lock().unwrap()) },
+                    _ => return
+                };
+                *levels_clone.lock().unwrap() = levels;
             });
         });
         Ok(())
@@ -225,23 +315,29 @@ impl AudioPlayer {
     pub fn get_volume(&self) -> f32{
         *self.volume.lock().unwrap()
     }
+    pub fn get_levels(&self) -> Levels{
+        *self.levels.lock().unwrap()
+    }
     pub fn current_track_is_active(&self) -> bool{
         self.current_track.as_ref().unwrap().lock().unwrap().is_active
     }
     pub fn set_timestamp(&self,timestamp:Duration){
         self.pause();
         std::thread::sleep(Duration::from_millis(5));
-        self.current_track.as_ref().unwrap().lock().unwrap().set_timestamp(timestamp);
+        let mut current_track = self.current_track.as_ref().unwrap().lock().unwrap();
+        let start = current_track.start;
+        current_track.set_timestamp(start + timestamp);
+        std::mem::drop(current_track);
         self.play();
     }
     pub fn get_current_sample(&self) -> Option<i16>{
-        self.current_track.as_ref().unwrap().lock().unwrap().current_sample.clone()
+        self.current_track.as_ref().unwrap().lock().unwrap().current_sample
     }
     pub fn move_timestamp_forward(&self,timestamp_delta:Duration){
         let current_track = self.current_track.as_ref().unwrap().lock().unwrap();
-        let mut timestamp = timestamp_delta+current_track.timestamp;
-        if timestamp>current_track.duration {
-            timestamp = current_track.duration
+        let mut timestamp = timestamp_delta+current_track.position();
+        if timestamp>current_track.length() {
+            timestamp = current_track.length()
         }
         std::mem::drop(current_track);
 
@@ -250,9 +346,9 @@ impl AudioPlayer {
     pub fn move_timestamp_back(&self,timestamp_delta:Duration){
         let current_track = self.current_track.as_ref().unwrap().lock().unwrap();
         let mut timestamp = Duration::from_secs(0);
-        if timestamp_delta<current_track.timestamp
+        if timestamp_delta<current_track.position()
         {
-            timestamp = current_track.timestamp - timestamp_delta;
+            timestamp = current_track.position() - timestamp_delta;
         }
         std::mem::drop(current_track);
         self.set_timestamp(timestamp);
@@ -260,34 +356,107 @@ impl AudioPlayer {
     pub fn get_timestamp(&self) -> Duration{
         match self.current_track.as_ref(){
             Some(track) =>{
-                track.lock().unwrap().timestamp
+                track.lock().unwrap().position()
             },
             None => Duration::from_secs(0)
         }
     }
-    pub fn get_track_meta(&self) -> Option<Ref<'_, MP3Metadata>> {
-        match self.current_track_meta.as_ref(){
-            Some(meta) =>Some(meta.borrow()),
-            None => None,
-        }
+    pub fn get_current_path(&self) -> Option<PathBuf> {
+        self.current_track.as_ref().map(|track| track.lock().unwrap().path.clone())
+    }
+    pub fn get_duration(&self) -> Option<Duration> {
+        self.current_track.as_ref().map(|track| track.lock().unwrap().length())
     }
     pub fn change_track(&mut self,track: TrackData){
-        let path= track.path.clone();
         *self.current_track.as_ref().unwrap().lock().unwrap() = track;
-        self.current_track_meta.as_ref().unwrap().replace(mp3_metadata::read_from_file(path).unwrap());
+        self.plays_started += 1;
         self.rebuild_stream();
     }
-    pub fn set_next_track_in_dir(&mut self){
-        if let Some(path) = self.file_manager.next(){
-            let track = TrackData::new(path);
-            self.change_track(track);
+    pub fn plays_started(&self) -> u64{
+        self.plays_started
+    }
+    // another track of the file that is already open only seeks to where it starts
+    fn switch_to(&mut self, path:PathBuf) -> Result<(), anyhow::Error>{
+        if let Some(segment) = self.file_manager.segment(&path) {
+            let mut current_track = self.current_track.as_ref().unwrap().lock().unwrap();
+            if current_track.audio == segment.audio {
+                current_track.set_segment(path, segment);
+                std::mem::drop(current_track);
+                self.plays_started += 1;
+                self.play();
+                return Ok(())
+            }
         }
+        let track = AudioPlayer::open(&self.file_manager, path)?;
+        self.change_track(track);
+        Ok(())
+    }
+    // tracks that could not be opened since the last call
+    pub fn take_skipped(&mut self) -> Vec<(PathBuf, anyhow::Error)>{
+        std::mem::take(&mut self.skipped)
     }
-    pub fn set_prev_track_in_dir(&mut self){
-        if let Some(path) = self.file_manager.prev(){
-            let track = TrackData::new(path);
-            self.change_track(track);
+    // walks the list with `step` until a track opens, broken files are marked and skipped
+    // if none of them opens the position goes back to the playing track and false is returned
+    fn set_track_in_dir(&mut self, step:fn(&mut FileManager) -> Option<PathBuf>) -> bool{
+        let position = self.file_manager.position();
+        // repeat-all never runs out of tracks, every entry is tried at most once
+        let mut attempts = self.file_manager.file_paths.len() + self.file_manager.queued().len();
+        // checked before stepping, a step takes a queued track off the queue
+        while attempts != 0 {
+            let path = match step(&mut self.file_manager){
+                Some(path) => path,
+                None => break,
+            };
+            attempts -= 1;
+            if self.file_manager.is_bad(&path) { continue }
+            match self.switch_to(path.clone()){
+                Ok(()) => return true,
+                Err(e) => {
+                    self.file_manager.mark_bad(&path, &e);
+                    self.skipped.push((path, e));
+                }
+            }
         }
+        if self.file_manager.position() != position {
+            self.file_manager.set_index(position);
+        }
+        false
+    }
+    // jumps to a position in the list, a broken file is marked and the position goes back
+    pub fn set_track_at(&mut self, position:usize) -> bool{
+        let previous = self.file_manager.position();
+        self.file_manager.set_index(position);
+        let path = self.file_manager.get_current();
+        match self.switch_to(path.clone()){
+            Ok(()) => true,
+            Err(e) => {
+                self.file_manager.mark_bad(&path, &e);
+                self.skipped.push((path, e));
+                self.file_manager.set_index(previous);
+                false
+            }
+        }
+    }
+    // opens the current track again from the start, used by repeat-one
+    pub fn replay_track(&mut self) -> bool{
+        let path = match self.get_current_path(){
+            Some(path) => path,
+            None => return false,
+        };
+        match self.switch_to(path.clone()){
+            Ok(()) => true,
+            Err(e) => {
+                self.file_manager.mark_bad(&path, &e);
+                self.skipped.push((path, e));
+                false
+            }
+        }
+    }
+    pub fn set_next_track_in_dir(&mut self) -> bool{
+        self.set_track_in_dir(FileManager::next)
+    }
+    pub fn set_prev_track_in_dir(&mut self) -> bool{
+        self.set_track_in_dir(FileManager::prev)
     }
     pub fn is_playing(&self) -> bool{
         *self.is_playing.borrow()
@@ -311,23 +480,38 @@ impl AudioPlayer {
         self.event_loop.destroy_stream(self.stream_id.deref().clone());
     }
 
-    fn write_data<T>(output: &mut cpal::OutputBuffer<T>, value_iterator: &mut TrackData, volume:f32)
+    fn write_data<T>(output: &mut cpal::OutputBuffer<T>, value_iterator: &mut TrackData, volume:f32) -> Levels
         where
             T: Sample,
     {
+        let mut levels = Levels::default();
+        let mut sum_of_squares = [0.0;2];
+        let mut samples = [0;2];
         for frame in output.chunks_mut(value_iterator.channels) {
 
-            for sample in frame.iter_mut() {
+            for (channel, sample) in frame.iter_mut().enumerate() {
                 let mut value = match value_iterator.get_sample() {
                     Some(data) => data as f32 / value_iterator.sample_rate as f32,
-                    None => {
-                        0.0;
-                        break
-                    }
+                    None => break
                 };
                 value*=volume;
                 *sample = T::from(&value);
+
+                let channel = channel.min(1);
+                levels.peak[channel] = levels.peak[channel].max(value.abs());
+                sum_of_squares[channel] += value*value;
+                samples[channel] += 1;
             }
         }
+        for channel in 0..2 {
+            if samples[channel] != 0 {
+                levels.rms[channel] = (sum_of_squares[channel]/samples[channel] as f32).sqrt();
+            }
+        }
+        if value_iterator.channels == 1 {
+            levels.peak[1] = levels.peak[0];
+            levels.rms[1] = levels.rms[0];
+        }
+        levels
     }
 }
@@ -0,0 +1,88 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use std::path::Path;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::bookmarks::{Bookmark, Bookmarks};
+
+// every bookmark of the library, grouped by track
+pub struct BookmarkView{
+    bookmarks:Bookmarks,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl BookmarkView{
+    pub fn new(bookmarks:Bookmarks, viewport_size:usize) -> BookmarkView{
+        BookmarkView{bookmarks,viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    pub fn bookmarks(&self) -> &Bookmarks{
+        &self.bookmarks
+    }
+    pub fn add(&mut self, bookmark:Bookmark) -> Result<(), anyhow::Error>{
+        let index = self.bookmarks.add(bookmark)?;
+        self.select(index);
+        Ok(())
+    }
+    pub fn remove_selected(&mut self) -> Result<(), anyhow::Error>{
+        self.bookmarks.remove(self.selected_index.get())?;
+        self.select(self.selected_index.get());
+        Ok(())
+    }
+    pub fn selected(&self) -> Option<&Bookmark>{
+        self.bookmarks.items().get(self.selected_index.get())
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.bookmarks.items().len().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    // `current` is the absolute path of the playing track, its bookmarks are marked
+    pub fn draw(&self, stdout:&mut Stdout, current:&Path, x:u16, mut y:u16, width:u16){
+        let column_width = relative_size(1.0/4.0,width) as usize;
+        let row_width = column_width*4+3;
+        let header = format!("Bookmarks in {}, Enter jumps, X removes", self.bookmarks.location().display());
+        graphics::draw_text(stdout,style(graphics::set_text_width(header, row_width)).on_dark_blue(),x,y).unwrap();
+        y+=1;
+        if let Some(error) = self.bookmarks.error() {
+            graphics::draw_text(stdout,style(graphics::set_text_width(error.to_string(), row_width)).red(),x,y).unwrap();
+            return
+        }
+        if self.bookmarks.items().is_empty(){
+            let empty = style(String::from("No bookmarks, I bookmarks the current position of the playing track")).dark_grey();
+            graphics::draw_text(stdout,empty,x,y).unwrap();
+            return
+        }
+        let start_index = self.start_index.get();
+        for (index,bookmark) in self.bookmarks.items().iter().enumerate().skip(start_index).take(self.viewport_size){
+            let is_current = bookmark.path == current;
+            let marker = if is_current { graphics::graphic_symbols::NOW_PLAYING } else { " " };
+            let file = bookmark.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            let text = format!("{} {} {:>9} {}",
+                               marker,
+                               graphics::set_text_width(bookmark.name.clone(), column_width*2),
+                               graphics::duration_to_mmss(bookmark.time),
+                               file);
+            let mut description = style(graphics::set_text_width(text, row_width));
+            if is_current{
+                description = description.green();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
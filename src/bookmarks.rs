@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use anyhow::anyhow;
+
+const BOOKMARKS_FILE:&str = "bookmarks.json";
+
+// used unless --bookmarks points somewhere else, e.g. to a file shared with the recordings
+pub fn default_location() -> PathBuf{
+    dirs::data_dir().map(|dir| dir.join("rplayer")).unwrap_or_default().join(BOOKMARKS_FILE)
+}
+
+// times are written as seconds so the file stays readable and editable by hand
+pub(crate) mod seconds{
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+    pub fn serialize<S:Serializer>(time:&Duration, serializer:S) -> Result<S::Ok, S::Error>{
+        serializer.serialize_f64(time.as_secs_f64())
+    }
+    pub fn deserialize<'de, D:Deserializer<'de>>(deserializer:D) -> Result<Duration, D::Error>{
+        Duration::try_from_secs_f64(f64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Bookmark{
+    pub path:PathBuf,
+    pub name:String,
+    #[serde(with = "seconds")]
+    pub time:Duration,
+}
+
+// named positions in tracks, kept in a JSON file
+pub struct Bookmarks{
+    location:PathBuf,
+    items:Vec<Bookmark>,
+    // a file that does not parse is never overwritten
+    error:Option<String>,
+}
+impl Bookmarks{
+    // tracks below the file's directory are stored relative to it, so a shared file works on every machine
+    pub fn open(location:&Path) -> Bookmarks{
+        let location = std::path::absolute(location).unwrap_or_else(|_| location.to_path_buf());
+        let mut bookmarks = Bookmarks{location,items:Vec::new(),error:None};
+        let text = match fs::read_to_string(&bookmarks.location){
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return bookmarks,
+            Err(e) => {
+                bookmarks.error = Some(format!("{} cannot be read: {}", bookmarks.location.display(), e));
+                return bookmarks
+            }
+        };
+        match serde_json::from_str::<Vec<Bookmark>>(&text){
+            Ok(items) => {
+                let dir = bookmarks.location.parent().unwrap_or_else(|| Path::new(""));
+                bookmarks.items = items.into_iter().map(|bookmark| Bookmark{path:dir.join(&bookmark.path), ..bookmark}).collect();
+                bookmarks.sort();
+            }
+            Err(e) => bookmarks.error = Some(format!("{} cannot be read: {}", bookmarks.location.display(), e)),
+        }
+        bookmarks
+    }
+    pub fn location(&self) -> &Path{
+        &self.location
+    }
+    pub fn error(&self) -> Option<&str>{
+        self.error.as_deref()
+    }
+    pub fn items(&self) -> &[Bookmark]{
+        &self.items
+    }
+    // grouped by track, in the order they come up
+    fn sort(&mut self){
+        self.items.sort_by(|a, b| a.path.cmp(&b.path).then(a.time.cmp(&b.time)));
+    }
+    // returns where the bookmark ended up in the list
+    pub fn add(&mut self, mut bookmark:Bookmark) -> Result<usize, anyhow::Error>{
+        self.check_writable()?;
+        bookmark.path = std::path::absolute(&bookmark.path)?;
+        let (path, time) = (bookmark.path.clone(), bookmark.time);
+        self.items.push(bookmark);
+        self.sort();
+        self.save()?;
+        Ok(self.items.iter().position(|bookmark| bookmark.path == path && bookmark.time == time).unwrap_or(0))
+    }
+    pub fn remove(&mut self, index:usize) -> Result<(), anyhow::Error>{
+        self.check_writable()?;
+        if index<self.items.len() {
+            self.items.remove(index);
+            self.save()?;
+        }
+        Ok(())
+    }
+    fn check_writable(&self) -> Result<(), anyhow::Error>{
+        match self.error.as_ref(){
+            Some(error) => Err(anyhow!("{}", error)),
+            None => Ok(()),
+        }
+    }
+    fn save(&self) -> Result<(), anyhow::Error>{
+        let dir = self.location.parent().unwrap_or_else(|| Path::new(""));
+        let items:Vec<Bookmark> = self.items.iter().map(|bookmark| Bookmark{
+            path:bookmark.path.strip_prefix(dir).map(Path::to_path_buf).unwrap_or_else(|_| bookmark.path.clone()),
+            ..bookmark.clone()
+        }).collect();
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+        // a temporary file first so an interrupted save keeps the old bookmarks
+        let tmp_location = self.location.with_extension("tmp");
+        fs::write(&tmp_location, serde_json::to_string_pretty(&items)? + "\n")?;
+        fs::rename(&tmp_location, &self.location)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn a_missing_file_is_no_bookmarks(){
+        let location = std::env::temp_dir().join(format!("rplayer-bookmarks-missing-{}.json", std::process::id()));
+        let bookmarks = Bookmarks::open(&location);
+        assert!(bookmarks.error().is_none());
+        assert!(bookmarks.items().is_empty());
+    }
+
+    #[test]
+    fn a_file_that_cannot_be_read_is_not_overwritten(){
+        // a directory in place of the file fails to read with something else than NotFound
+        let location = std::env::temp_dir().join(format!("rplayer-bookmarks-dir-{}", std::process::id()));
+        fs::create_dir_all(&location).unwrap();
+        let mut bookmarks = Bookmarks::open(&location);
+        assert!(bookmarks.error().is_some());
+        assert!(bookmarks.add(Bookmark{path:PathBuf::from("/music/a.mp3"),name:String::from("a"),time:Duration::from_secs(1)}).is_err());
+        assert!(location.is_dir());
+        fs::remove_dir_all(&location).unwrap();
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::id3::{self, Chapter};
+
+// going back within this much of a chapter's start goes to the chapter before
+const RESTART_THRESHOLD:Duration = Duration::from_secs(3);
+
+// the chapters of the playing track, read from its ID3 CHAP frames
+pub struct ChapterView{
+    track:Option<PathBuf>,
+    chapters:Vec<Chapter>,
+    current:Cell<Option<usize>>,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl ChapterView{
+    pub fn new(viewport_size:usize) -> ChapterView{
+        ChapterView{track:None,chapters:Vec::new(),current:Cell::new(None),viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    // loads the chapters when the track changed, true if it did
+    pub fn set_track(&mut self, path:&Path) -> bool{
+        if self.track.as_deref() == Some(path) { return false }
+        self.track = Some(path.to_path_buf());
+        self.chapters = id3::Tag::read(path).ok().flatten().map(|tag| tag.chapters()).unwrap_or_default();
+        self.current.set(None);
+        self.select(0);
+        true
+    }
+    pub fn starts(&self) -> Vec<Duration>{
+        self.chapters.iter().map(|chapter| chapter.start).collect()
+    }
+    fn current_chapter(&self, timestamp:Duration) -> Option<usize>{
+        self.chapters.iter().rposition(|chapter| chapter.start<=timestamp)
+    }
+    // moves the highlight to the chapter at the timestamp, the cursor goes along unless it was moved away
+    pub fn update(&self, timestamp:Duration){
+        let current = self.current_chapter(timestamp);
+        if current == self.current.get() { return }
+        let is_following = self.current.get().unwrap_or(0) == self.selected_index.get();
+        self.current.set(current);
+        if let (true, Some(current)) = (is_following, current) {
+            self.select(current);
+        }
+    }
+    pub fn next_start(&self, timestamp:Duration) -> Option<Duration>{
+        self.chapters.iter().map(|chapter| chapter.start).find(|start| *start>timestamp)
+    }
+    // the start of the current chapter, or of the one before when it just started
+    pub fn prev_start(&self, timestamp:Duration) -> Option<Duration>{
+        let current = self.current_chapter(timestamp)?;
+        let start = self.chapters[current].start;
+        if timestamp.saturating_sub(start)>RESTART_THRESHOLD || current == 0 {
+            Some(start)
+        } else {
+            Some(self.chapters[current-1].start)
+        }
+    }
+    pub fn selected_time(&self) -> Option<Duration>{
+        self.chapters.get(self.selected_index.get()).map(|chapter| chapter.start)
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.chapters.len().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let header = style(graphics::set_text_width(String::from("Chapters, Enter jumps to the selected one"), row_width)).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
+        y+=1;
+        if self.chapters.is_empty(){
+            let empty = style(String::from("No chapters, they are read from the CHAP frames of the track's tag")).dark_grey();
+            graphics::draw_text(stdout,empty,x,y).unwrap();
+            return
+        }
+        let start_index = self.start_index.get();
+        for (index,chapter) in self.chapters.iter().enumerate().skip(start_index).take(self.viewport_size){
+            let is_current = Some(index) == self.current.get();
+            let marker = if is_current { graphics::graphic_symbols::NOW_PLAYING } else { " " };
+            let title = if chapter.title.is_empty() { format!("Chapter {}", index+1) } else { chapter.title.clone() };
+            let text = format!("{} {:>9}  {}", marker, graphics::duration_to_mmss(chapter.start), title);
+            let mut description = style(graphics::set_text_width(text, row_width));
+            if is_current{
+                description = description.green();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::anyhow;
+use crate::file_manager::is_mp3;
+use crate::library::TrackInfo;
+
+pub fn is_cue(path:&Path) -> bool{
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("cue"))
+}
+
+// the part of an audio file a cue track plays, the last track of a file runs to its end
+#[derive(Clone)]
+pub struct Segment{
+    pub audio:PathBuf,
+    pub start:Duration,
+    pub end:Option<Duration>,
+}
+
+struct Track{
+    number:u32,
+    title:String,
+    performer:String,
+    file:PathBuf,
+    // INDEX 01, tracks without one are left out
+    start:Option<Duration>,
+}
+
+pub struct CueSheet{
+    title:String,
+    performer:String,
+    genre:String,
+    year:Option<u32>,
+    tracks:Vec<Track>,
+}
+
+// "text in quotes" or a single word
+fn value(rest:&str) -> String{
+    match rest.strip_prefix('"'){
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => rest.split_whitespace().next().unwrap_or_default().to_string(),
+    }
+}
+
+// "mm:ss:ff" with 75 frames a second
+fn parse_time(text:&str) -> Option<Duration>{
+    let mut parts = text.trim().split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::from_millis((minutes*60+seconds)*1000 + frames*1000/75))
+}
+
+// sheets often still name the .wav or .flac the album was ripped to before it was converted
+fn resolve_file(dir:&Path, name:&str) -> PathBuf{
+    let path = dir.join(name);
+    let converted = path.with_extension("mp3");
+    if !path.is_file() && converted.is_file() { converted } else { path }
+}
+
+impl CueSheet{
+    pub fn read(path:&Path) -> Result<CueSheet, anyhow::Error>{
+        let text = fs::read(path)?;
+        // sheets are utf-8 or latin-1, sometimes with a byte order mark
+        let text = match String::from_utf8(text){
+            Ok(text) => text,
+            Err(e) => e.into_bytes().iter().map(|byte| *byte as char).collect(),
+        };
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        let sheet = CueSheet::parse(text.trim_start_matches('\u{feff}'), dir);
+        if sheet.tracks.is_empty() { return Err(anyhow!("{} has no tracks", path.display())) }
+        Ok(sheet)
+    }
+    // TITLE and PERFORMER before the first TRACK are the album's, after it the track's
+    fn parse(text:&str, dir:&Path) -> CueSheet{
+        let mut sheet = CueSheet{title:String::new(),performer:String::new(),genre:String::new(),year:None,tracks:Vec::new()};
+        let mut file:Option<PathBuf> = None;
+        for line in text.lines(){
+            let (command, rest) = line.trim().split_once(char::is_whitespace).unwrap_or((line.trim(), ""));
+            let rest = rest.trim();
+            match (command.to_ascii_uppercase().as_str(), sheet.tracks.last_mut()){
+                ("FILE", _) => {
+                    // the type comes after the name, names without quotes can still have spaces
+                    let name = match rest.starts_with('"'){
+                        true => value(rest),
+                        false => rest.rsplit_once(char::is_whitespace).map_or(rest, |(name, _)| name.trim()).to_string(),
+                    };
+                    file = Some(resolve_file(dir, &name));
+                }
+                ("TRACK", _) => {
+                    if let (Some(file), Ok(number)) = (file.clone(), value(rest).parse()) {
+                        sheet.tracks.push(Track{number,title:String::new(),performer:String::new(),file,start:None});
+                    }
+                }
+                ("TITLE", Some(track)) => track.title = value(rest),
+                ("TITLE", None) => sheet.title = value(rest),
+                ("PERFORMER", Some(track)) => track.performer = value(rest),
+                ("PERFORMER", None) => sheet.performer = value(rest),
+                ("INDEX", Some(track)) => {
+                    if let Some(("01", time)) = rest.split_once(char::is_whitespace) {
+                        track.start = parse_time(time);
+                    }
+                }
+                ("REM", _) => match rest.split_once(char::is_whitespace){
+                    Some((key, text)) if key.eq_ignore_ascii_case("GENRE") => sheet.genre = value(text.trim()),
+                    Some((key, text)) if key.eq_ignore_ascii_case("DATE") => sheet.year = value(text.trim()).get(..4).and_then(|year| year.parse().ok()),
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+        sheet.tracks.retain(|track| track.start.is_some());
+        sheet
+    }
+    // every track with its virtual path "album.cue#03", the part of the file it plays and what the sheet says about it
+    pub fn tracks(&self, path:&Path) -> Vec<(PathBuf, Segment, TrackInfo)>{
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        self.tracks.iter().enumerate().map(|(index, track)| {
+            let start = track.start.unwrap_or_default();
+            // a track ends where the next one of the same file starts
+            let end = self.tracks.get(index+1).filter(|next| next.file == track.file).and_then(|next| next.start);
+            let info = TrackInfo{
+                title:if track.title.is_empty() { format!("Track {:02}", track.number) } else { track.title.clone() },
+                artist:if track.performer.is_empty() { self.performer.clone() } else { track.performer.clone() },
+                album:self.title.clone(),
+                album_artist:self.performer.clone(),
+                track_number:Some(track.number),
+                year:self.year,
+                genre:self.genre.clone(),
+                ..TrackInfo::default()
+            };
+            (path.with_file_name(format!("{}#{:02}", name, track.number)), Segment{audio:track.file.clone(),start,end}, info)
+        }).collect()
+    }
+}
+
+// the tracks of the sheets whose audio files are all in the list replace those files
+pub fn expand(sheets:&[PathBuf], file_paths:&mut Vec<PathBuf>) -> Vec<(PathBuf, Segment, TrackInfo)>{
+    let known:HashSet<PathBuf> = file_paths.iter().cloned().collect();
+    let mut replaced = HashSet::new();
+    let mut tracks = Vec::new();
+    for path in sheets{
+        let sheet_tracks = match CueSheet::read(path){
+            Ok(sheet) => sheet.tracks(path),
+            Err(_) => continue,
+        };
+        // only mp3 files play, a second sheet for the same file is ignored
+        let is_playable = sheet_tracks.iter().all(|(_, segment, _)| {
+            is_mp3(&segment.audio) && known.contains(&segment.audio) && !replaced.contains(&segment.audio)
+        });
+        if !is_playable { continue }
+        replaced.extend(sheet_tracks.iter().map(|(_, segment, _)| segment.audio.clone()));
+        tracks.extend(sheet_tracks);
+    }
+    file_paths.retain(|path| !replaced.contains(path));
+    file_paths.extend(tracks.iter().map(|(path, _, _)| path.clone()));
+    tracks
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const SHEET:&str = "\
+REM GENRE \"Jazz\"
+REM DATE 1959
+PERFORMER \"The Band\"
+TITLE \"The Album\"
+FILE \"side a.mp3\" MP3
+  TRACK 01 AUDIO
+    TITLE \"First\"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    PERFORMER \"Guest\"
+    INDEX 00 03:58:00
+    INDEX 01 04:00:37
+FILE side b.mp3 MP3
+  TRACK 03 AUDIO
+    INDEX 01 00:00:00
+  TRACK 04 AUDIO
+    TITLE \"No index\"
+";
+
+    #[test]
+    fn parse_time_counts_75_frames_a_second(){
+        assert_eq!(parse_time("04:00:37"), Some(Duration::from_millis(240493)));
+        assert_eq!(parse_time("61:02:74"), Some(Duration::from_millis(3662986)));
+        assert_eq!(parse_time("4:00"), None);
+    }
+
+    #[test]
+    fn tracks_start_at_index_01(){
+        let dir = Path::new("/music/album");
+        let sheet = CueSheet::parse(SHEET, dir);
+        let tracks = sheet.tracks(&dir.join("album.cue"));
+        // a track without INDEX 01 is left out
+        assert_eq!(tracks.len(), 3);
+        let (path, segment, info) = &tracks[1];
+        assert_eq!(path, &dir.join("album.cue#02"));
+        assert_eq!(segment.start, Duration::from_millis(240493));
+        assert_eq!(info.artist, "Guest");
+        assert_eq!(info.album_artist, "The Band");
+        assert_eq!(info.album, "The Album");
+        assert_eq!(info.genre, "Jazz");
+        assert_eq!(info.year, Some(1959));
+        assert_eq!(tracks[0].2.title, "First");
+        assert_eq!(tracks[0].2.artist, "The Band");
+        assert_eq!(tracks[2].2.title, "Track 03");
+    }
+
+    #[test]
+    fn tracks_end_where_the_next_one_of_their_file_starts(){
+        let dir = Path::new("/music/album");
+        let tracks = CueSheet::parse(SHEET, dir).tracks(&dir.join("album.cue"));
+        assert_eq!(tracks[0].1.end, Some(Duration::from_millis(240493)));
+        // the last track of a file runs to its end
+        assert_eq!(tracks[1].1.end, None);
+        assert_eq!(tracks[2].1.end, None);
+    }
+
+    #[test]
+    fn file_names_with_and_without_quotes(){
+        let dir = Path::new("/music/album");
+        let tracks = CueSheet::parse(SHEET, dir).tracks(&dir.join("album.cue"));
+        assert_eq!(tracks[0].1.audio, dir.join("side a.mp3"));
+        assert_eq!(tracks[2].1.audio, dir.join("side b.mp3"));
+    }
+
+    #[test]
+    fn a_converted_file_replaces_the_missing_original(){
+        let dir = std::env::temp_dir().join(format!("rplayer-cue-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("album.mp3"), b"").unwrap();
+        fs::write(dir.join("other.flac"), b"").unwrap();
+        assert_eq!(resolve_file(&dir, "album.wav"), dir.join("album.mp3"));
+        // a file that is there is kept even when it cannot be played
+        assert_eq!(resolve_file(&dir, "other.flac"), dir.join("other.flac"));
+        assert_eq!(resolve_file(&dir, "missing.wav"), dir.join("missing.wav"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+use std::cell::Cell;
+use std::fs;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::file_manager::{is_mp3, ScanOptions};
+
+const FAVOURITES_FILE:&str = "favourite_folders";
+
+pub fn favourites_location() -> Option<PathBuf>{
+    dirs::config_dir().map(|dir| dir.join("rplayer").join(FAVOURITES_FILE))
+}
+
+// subdirectories of one directory, with the favourite folders listed above them
+pub struct DirectoryBrowser{
+    dir:PathBuf,
+    subdirs:Vec<(PathBuf, bool)>,
+    favourites:Vec<PathBuf>,
+    options:ScanOptions,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl DirectoryBrowser{
+    // starts in `dir`, or in the closest parent that still exists
+    pub fn new(dir:&Path, options:ScanOptions, viewport_size:usize) -> DirectoryBrowser{
+        let favourites = favourites_location()
+            .and_then(|location| fs::read_to_string(location).ok())
+            .map(|text| text.lines().filter(|line| !line.is_empty()).map(PathBuf::from).collect())
+            .unwrap_or_default();
+        let dir = dir.ancestors().find(|dir| dir.is_dir()).map(Path::to_path_buf)
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_else(|| PathBuf::from("/"));
+        let mut browser = DirectoryBrowser{dir,subdirs:Vec::new(),favourites,options,viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)};
+        browser.read_dir();
+        browser
+    }
+    pub fn current_dir(&self) -> &Path{
+        &self.dir
+    }
+    // the flag tells whether the directory has mp3 files right in it
+    fn read_dir(&mut self){
+        let mut subdirs:Vec<PathBuf> = fs::read_dir(&self.dir).map(|entries| entries.flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && !self.options.is_ignored(path))
+            .collect()).unwrap_or_default();
+        subdirs.sort_by_key(|path| path.file_name().map(|name| name.to_string_lossy().to_lowercase()));
+        self.subdirs = subdirs.into_iter().map(|path| {
+            let has_mp3 = fs::read_dir(&path).map(|mut entries| entries.any(|entry| entry.is_ok_and(|entry| is_mp3(&entry.path())))).unwrap_or(false);
+            (path, has_mp3)
+        }).collect();
+    }
+    fn rows(&self) -> usize{
+        self.favourites.len() + self.subdirs.len()
+    }
+    pub fn selected(&self) -> Option<PathBuf>{
+        let index = self.selected_index.get();
+        match self.favourites.get(index){
+            Some(favourite) => Some(favourite.clone()),
+            None => self.subdirs.get(index-self.favourites.len()).map(|(path, _)| path.clone()),
+        }
+    }
+    pub fn open(&mut self, dir:PathBuf){
+        self.dir = dir;
+        self.read_dir();
+        self.select(self.favourites.len());
+    }
+    pub fn open_selected(&mut self){
+        if let Some(dir) = self.selected().filter(|dir| dir.is_dir()){
+            self.open(dir);
+        }
+    }
+    // the directory we came from stays selected
+    pub fn open_parent(&mut self){
+        let child = self.dir.clone();
+        if let Some(parent) = child.parent(){
+            self.open(parent.to_path_buf());
+            if let Some(index) = self.subdirs.iter().position(|(path, _)| *path == child){
+                self.select(self.favourites.len()+index);
+            }
+        }
+    }
+    // adds or removes the selected folder, or the open one when nothing is selected
+    pub fn toggle_favourite(&mut self) -> Result<bool, anyhow::Error>{
+        let dir = self.selected().unwrap_or_else(|| self.dir.clone());
+        let dir = dir.canonicalize().unwrap_or(dir);
+        let is_favourite = match self.favourites.iter().position(|favourite| *favourite == dir){
+            Some(index) => { self.favourites.remove(index); false }
+            None => { self.favourites.push(dir); true }
+        };
+        self.select(self.selected_index.get());
+        let location = favourites_location().ok_or_else(|| anyhow::anyhow!("no config directory for the favourite folders"))?;
+        if let Some(dir) = location.parent(){
+            fs::create_dir_all(dir)?;
+        }
+        let text:String = self.favourites.iter().map(|favourite| format!("{}\n", favourite.display())).collect();
+        fs::write(location, text)?;
+        Ok(is_favourite)
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.rows().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let header = style(graphics::set_text_width(format!("{}", self.dir.display()), row_width)).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
+        y+=1;
+        if self.rows() == 0{
+            let empty = style(String::from("No folders here, Backspace goes to the parent folder")).dark_grey();
+            graphics::draw_text(stdout,empty,x,y).unwrap();
+            return
+        }
+        let favourites = self.favourites.iter().map(|favourite| (format!("★ {}", favourite.display()), false, true));
+        let subdirs = self.subdirs.iter().map(|(path, has_mp3)| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            (format!("  {}/", name), *has_mp3, false)
+        });
+        let start_index = self.start_index.get();
+        for (index,(text, has_mp3, is_favourite)) in favourites.chain(subdirs).enumerate().skip(start_index).take(self.viewport_size){
+            let mut description = style(graphics::set_text_width(text, row_width));
+            if is_favourite{
+                description = description.yellow();
+            }
+            else if has_mp3{
+                description = description.green();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
@@ -1,27 +1,185 @@
 use std::cell::Cell;
+use std::collections::HashSet;
 use crate::{graphics, relative_size};
 use std::io::Stdout;
-use crate::file_manager::FileManager;
+use crate::file_manager::{FileManager, SortKey};
+use crate::library::{Rating, TrackInfo};
+use crate::history::{self, History, PlayStats};
+use crate::search::fuzzy_match;
 use crossterm::style::{style};
 use crossterm::style::Colorize;
 
 use lru_cache::LruCache;
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use unicode_width::UnicodeWidthStr;
 
+// how many chars of `text` set_text_width keeps before cutting it off with dots
+fn visible_chars(text:&str, width:usize) -> usize{
+    if UnicodeWidthStr::width(text)<=width{
+        return text.chars().count()
+    }
+    let mut shown = String::new();
+    for c in text.chars(){
+        if UnicodeWidthStr::width(shown.as_str())>=width-3 { break }
+        shown.push(c);
+    }
+    shown.chars().count()
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Column{
+    Filename,
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    TrackNumber,
+    DiscNumber,
+    Year,
+    Genre,
+    Comment,
+    Duration,
+    PlayCount,
+    LastPlayed,
+    Rating,
+}
+impl Column{
+    pub const DEFAULT:[Column; 4] = [Column::Filename, Column::Title, Column::Artist, Column::Duration];
+    pub fn parse(name:&str) -> Option<Column>{
+        match name.trim().to_ascii_lowercase().as_str(){
+            "filename" => Some(Column::Filename),
+            "title" => Some(Column::Title),
+            "artist" => Some(Column::Artist),
+            "album-artist" => Some(Column::AlbumArtist),
+            "album" => Some(Column::Album),
+            "track" => Some(Column::TrackNumber),
+            "disc" => Some(Column::DiscNumber),
+            "year" => Some(Column::Year),
+            "genre" => Some(Column::Genre),
+            "comment" => Some(Column::Comment),
+            "duration" => Some(Column::Duration),
+            "plays" => Some(Column::PlayCount),
+            "last-played" => Some(Column::LastPlayed),
+            "rating" => Some(Column::Rating),
+            _ => None,
+        }
+    }
+    fn header(self) -> &'static str{
+        match self{
+            Column::Filename => "Filename",
+            Column::Title => "Title",
+            Column::Artist => "Artist",
+            Column::AlbumArtist => "Album artist",
+            Column::Album => "Album",
+            Column::TrackNumber => "Track",
+            Column::DiscNumber => "Disc",
+            Column::Year => "Year",
+            Column::Genre => "Genre",
+            Column::Comment => "Comment",
+            Column::Duration => "Duration",
+            Column::PlayCount => "Plays",
+            Column::LastPlayed => "Last played",
+            Column::Rating => "Rating",
+        }
+    }
+    fn sort_key(self) -> Option<SortKey>{
+        match self{
+            Column::Filename => Some(SortKey::Filename),
+            Column::Title => Some(SortKey::Title),
+            Column::Artist => Some(SortKey::Artist),
+            Column::Album => Some(SortKey::Album),
+            Column::TrackNumber => Some(SortKey::TrackNumber),
+            Column::Year => Some(SortKey::Year),
+            Column::Duration => Some(SortKey::Duration),
+            _ => None,
+        }
+    }
+    // the columns the search looks at
+    fn is_searched(self) -> bool{
+        matches!(self, Column::Filename | Column::Title | Column::Artist | Column::Album)
+    }
+    fn text(self, path:&Path, info:&TrackInfo, stats:&PlayStats) -> String{
+        let number = |number:Option<u32>| number.map(|number| number.to_string()).unwrap_or_default();
+        match self{
+            Column::Filename => path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            Column::Title => info.title.clone(),
+            Column::Artist => info.artist.clone(),
+            Column::AlbumArtist => info.album_artist.clone(),
+            Column::Album => info.album.clone(),
+            Column::TrackNumber => number(info.track_number),
+            Column::DiscNumber => number(info.disc_number),
+            Column::Year => number(info.year),
+            Column::Genre => info.genre.clone(),
+            Column::Comment => info.comment.clone(),
+            Column::Duration => graphics::duration_to_mmss(info.duration),
+            Column::PlayCount => stats.count.to_string(),
+            Column::LastPlayed => stats.last_played.map(history::format_local).unwrap_or_default(),
+            Column::Rating => {
+                let favourite = if info.rating.is_favourite { format!("{} ", graphics::graphic_symbols::FAVOURITE) } else { String::new() };
+                let stars = info.rating.stars.min(5) as usize;
+                match stars{
+                    0 => favourite,
+                    _ => favourite + &graphics::graphic_symbols::STAR.repeat(stars) + &graphics::graphic_symbols::EMPTY_STAR.repeat(5-stars),
+                }
+            }
+        }
+    }
+}
+
+// which tracks the explorer lists, by their rating
+#[derive(Clone, Copy, PartialEq)]
+pub enum RatingFilter{
+    All,
+    Favourites,
+    AtLeast(u8),
+}
+impl RatingFilter{
+    // all, favourites, then from 1 star up to 5
+    pub fn next(self) -> RatingFilter{
+        match self{
+            RatingFilter::All => RatingFilter::Favourites,
+            RatingFilter::Favourites => RatingFilter::AtLeast(1),
+            RatingFilter::AtLeast(stars) if stars<5 => RatingFilter::AtLeast(stars+1),
+            RatingFilter::AtLeast(_) => RatingFilter::All,
+        }
+    }
+    pub fn label(self) -> String{
+        match self{
+            RatingFilter::All => String::from("ALL RATINGS"),
+            RatingFilter::Favourites => String::from("FAVOURITES"),
+            RatingFilter::AtLeast(stars) => format!("{}+", graphics::graphic_symbols::STAR.repeat(stars as usize)),
+        }
+    }
+    pub fn matches(self, rating:Rating) -> bool{
+        match self{
+            RatingFilter::All => true,
+            RatingFilter::Favourites => rating.is_favourite,
+            RatingFilter::AtLeast(stars) => rating.stars>=stars,
+        }
+    }
+}
+
+// rows are positions in the FileManager order, or only some of them while a filter is set
 pub struct FileExplorer{
     files: usize,
+    total_files: usize,
+    filter: Option<Vec<usize>>,
+    query: String,
+    columns: Vec<Column>,
     viewport_size: usize,
+    max_viewport_size: usize,
     highlighted_index:Cell<usize>,
+    playing_index:Cell<usize>,
     start_index: Cell<usize>,
     cache: LruCache<PathBuf,String>,
+    // tracks picked for a batch edit, by path so they survive sorting
+    marked: HashSet<PathBuf>,
 }
 impl FileExplorer{
-    pub fn new(files:usize,mut viewport_size:usize) -> FileExplorer{
-        if files<viewport_size{
-            viewport_size = files;
-        }
-        let file_explorer = FileExplorer{files,viewport_size,start_index:Cell::new(0),highlighted_index:Cell::new(0),cache:LruCache::new(100)};
+    pub fn new(files:usize,max_viewport_size:usize) -> FileExplorer{
+        let viewport_size = max_viewport_size.min(files);
+        let file_explorer = FileExplorer{files,total_files:files,filter:None,query:String::new(),columns:Column::DEFAULT.to_vec(),viewport_size,max_viewport_size,start_index:Cell::new(0),highlighted_index:Cell::new(0),playing_index:Cell::new(0),cache:LruCache::new(100),marked:HashSet::new()};
         file_explorer.set_viewport(0);
         file_explorer
     }
@@ -36,94 +194,204 @@ impl FileExplorer{
         }
         self.start_index.set(start_index);
     }
-    pub fn move_viewport_down(&self){
-        if self.start_index.get()<self.max_start_index_value(){
-            self.set_viewport(self.start_index.get()+1);
+    pub fn set_highlight(&self,position:usize){
+        if position <self.files {
+            self.highlighted_index.set(position);
         }
     }
-    pub fn move_viewport_up(&self){
-        if self.start_index.get()>0{
-            self.set_viewport(self.start_index.get()-1);
+    fn position(&self,row:usize) -> usize{
+        match self.filter.as_ref(){
+            Some(filter) => filter[row],
+            None => row,
         }
     }
-    pub fn move_highlight_by(&self,n:i32){
-        let new_index= self.highlighted_index.get() as i32 + n;
-        if new_index >=0 && new_index <self.files as i32{
-            self.highlighted_index.set(new_index as usize);
+    fn row_of(&self,position:usize) -> Option<usize>{
+        match self.filter.as_ref(){
+            Some(filter) => filter.iter().position(|filtered| *filtered == position),
+            None => Some(position).filter(|position| *position<self.files),
         }
-
     }
-    pub fn set_highlight(&self,position:usize){
-        if position <self.files as usize {
-            self.highlighted_index.set(position);
+    // position of the highlighted row, None when the filter left no rows
+    pub fn highlighted(&self) -> Option<usize>{
+        Some(self.highlighted_index.get()).filter(|_| self.files != 0).map(|row| self.position(row))
+    }
+    pub fn set_columns(&mut self,columns:Vec<Column>){
+        self.columns = columns;
+        self.cache.clear();
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    // the list changed, positions of a filter are no longer valid
+    pub fn set_files(&mut self,files:usize){
+        let highlighted = self.highlighted();
+        self.filter = None;
+        self.total_files = files;
+        self.files = files;
+        self.viewport_size = self.max_viewport_size.min(files);
+        self.set_viewport(self.start_index.get());
+        self.playing_index.set(self.playing_index.get().min(files-1));
+        self.select(highlighted.unwrap_or_else(|| self.playing_index.get()));
+    }
+    // only shows the given positions, None shows the whole list again
+    pub fn set_filter(&mut self,filter:Option<Vec<usize>>){
+        self.files = filter.as_ref().map_or(self.total_files, Vec::len);
+        self.filter = filter;
+        self.viewport_size = self.max_viewport_size.min(self.files);
+        self.start_index.set(0);
+        self.highlighted_index.set(0);
+    }
+    // characters matching the query are highlighted in the rows
+    pub fn set_query(&mut self,query:&str){
+        self.query = query.to_string();
+    }
+    // highlights the row and scrolls just enough to show it
+    pub fn select(&self,position:usize){
+        if self.files == 0 { return }
+        let position = position.min(self.files-1);
+        self.set_highlight(position);
+        if position<self.start_index.get(){
+            self.set_viewport(position);
+        }
+        else if position>=self.start_index.get()+self.viewport_size{
+            self.set_viewport(position+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self,n:i32){
+        self.select((self.highlighted_index.get() as i32 + n).max(0) as usize);
+    }
+    // marks the playing row, the cursor goes along unless it was moved away from the playing row
+    pub fn follow(&self,position:usize){
+        let is_following = self.highlighted() == Some(self.playing_index.get());
+        self.playing_index.set(position);
+        if is_following{
+            self.select_position(position);
         }
     }
-    pub fn move_down(&self){
-        let index = self.highlighted_index.get();
-        if index-self.start_index.get() == self.viewport_size-1{
-            self.move_viewport_down();
+    // puts the cursor back on the playing row, e.g. after the order changed
+    pub fn jump_to(&self,position:usize){
+        self.playing_index.set(position);
+        self.select_position(position);
+    }
+    // selects the row showing a position, if the filter shows it at all
+    pub fn select_position(&self,position:usize){
+        if let Some(row) = self.row_of(position){
+            self.select(row);
         }
-        self.move_highlight_by(1);
     }
-    pub fn move_up(&self){
-        if self.highlighted_index.get()-self.start_index.get() == 0{
-            self.move_viewport_up();
+    // the row of the position or of the first one shown after it, e.g. when the filter now hides it
+    pub fn select_nearest(&self,position:usize){
+        match self.filter.as_ref(){
+            Some(filter) => self.select(filter.partition_point(|shown| *shown<position)),
+            None => self.select(position),
         }
-        self.move_highlight_by(-1);
-
+    }
+    pub fn invalidate(&mut self,path:&Path){
+        self.cache.remove(path);
+    }
+    pub fn toggle_mark(&mut self,path:PathBuf){
+        if !self.marked.remove(&path){
+            self.marked.insert(path);
+        }
+    }
+    pub fn clear_marks(&mut self){
+        self.marked.clear();
+    }
+    // the marked tracks in list order
+    pub fn marked(&self,file_manager:&FileManager) -> Vec<PathBuf>{
+        if self.marked.is_empty() { return Vec::new() }
+        (0..self.total_files).map(|position| file_manager.path_at(position)).filter(|path| self.marked.contains(path)).collect()
     }
 
-
-
-    pub fn draw(&mut self, stdout:&mut Stdout, file_manager:&mut FileManager, x:u16, mut y:u16, width:u16){
+    pub fn draw(&mut self, stdout:&mut Stdout, file_manager:&mut FileManager, history:&History, x:u16, mut y:u16, width:u16){
         let start_index = self.start_index.get();
         let end_index = start_index + self.viewport_size;
-        let padding = relative_size(1.0/4.0,width) as usize;
-        let mut header = style(format!("{} {} {} {}",
-                                       graphics::set_text_width(String::from("Filename"),padding),
-                                       graphics::set_text_width(String::from("Track"),padding),
-                                       graphics::set_text_width(String::from("Artist"),padding),
-                                       graphics::set_text_width(String::from("Duration"),padding)
-        ));
-        header = header.on_dark_blue();
-        graphics::draw_text(stdout,header,x,y as u16).unwrap();
+        // narrow columns still have room for a few characters and the dots
+        let padding = (relative_size(1.0/self.columns.len() as f32,width) as usize).max(8);
+        // the first column gives up room for the row number
+        let widths:Vec<usize> = (0..self.columns.len()).map(|index| if index == 0 { padding-5 } else { padding }).collect();
+        let offsets:Vec<usize> = widths.iter().scan(0, |offset, width| { let start = *offset; *offset += width+1; Some(start) }).collect();
+        // the column the list is sorted by gets an arrow
+        let (sort_key, descending) = file_manager.sort_order();
+        let arrow = if descending { graphics::graphic_symbols::SORT_DESCENDING } else { graphics::graphic_symbols::SORT_ASCENDING };
+        let header:Vec<String> = self.columns.iter().map(|column| {
+            let name = if column.sort_key() == Some(sort_key) { format!("{} {}", column.header(), arrow) } else { column.header().to_string() };
+            graphics::set_text_width(name,padding)
+        }).collect();
+        let header = style(header.join(" ")).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
         y+=1;
-        for (index,path) in file_manager.get_paths(start_index,end_index).iter().enumerate(){
-
-            let file_info = match self.cache.get_mut(path){
+        let positions:Vec<usize> = (start_index..end_index).map(|row| self.position(row)).collect();
+        for (index,position) in positions.into_iter().enumerate(){
+            let path = &file_manager.path_at(position);
+            let mut file_info = match self.cache.get_mut(path){
                 Some(desc) =>desc.deref().clone(),
                 None =>{
-                    let meta = file_manager.get_metadata(&path);
-                    let time = graphics::set_text_width(format!("{}",graphics::duration_to_mmss(meta.duration)),padding);
-                    let mut title = String::from(" ");
-                    let mut artist = String::from(" ");
-                    if meta.optional_info.len()!=0{
-                        if let Some(maybe_title) = meta.optional_info[0].title.as_ref(){
-                            title = maybe_title.clone().trim_matches(char::from(0)).to_string();
-                        }
-                    }
-                    if let Some(tag) = meta.tag.as_ref(){
-                        artist = tag.artist.clone().trim_matches(char::from(0)).to_string();
-                    }
-                    title = graphics::set_text_width(title, padding);
-                    artist = graphics::set_text_width(artist, padding);
-
-                    let desc = format!("{} {} {} {}",
-                                                        graphics::set_text_width(String::from(path.file_name().unwrap().to_str().unwrap_or("ERROR READING!")),padding-5),
-                                                        title,
-                                                        artist,
-                                                        time
-                    );
+                    let info = file_manager.get_metadata(path).unwrap_or_default();
+                    let stats = history.stats(path);
+                    let cells:Vec<String> = self.columns.iter().zip(widths.iter())
+                        .map(|(column, width)| graphics::set_text_width(column.text(path, &info, &stats), *width))
+                        .collect();
+                    let desc = cells.join(" ");
                     self.cache.insert(path.clone(),desc.clone());
                     desc
                 }
             };
-            let display_index = graphics::set_text_width(format!("{}.",index+self.start_index.get()),5);
-            let mut description = style(format!("{}{}",display_index,file_info));
-            if index+start_index == self.highlighted_index.get(){
+            // the reason replaces the tag columns while the file is broken
+            let bad_reason = file_manager.bad_reason(path);
+            if let Some(reason) = bad_reason {
+                let filename = graphics::set_text_width(Column::Filename.text(path, &TrackInfo::default(), &PlayStats::default()),padding-5);
+                let rest = ((padding+1)*(self.columns.len()-1)).saturating_sub(1);
+                file_info = if rest<3 { filename } else { format!("{} {}", filename, graphics::set_text_width(format!("Unreadable: {}", reason), rest)) };
+            }
+            let is_playing = position == self.playing_index.get();
+            let marker = if is_playing { graphics::graphic_symbols::NOW_PLAYING } else if self.marked.contains(path) { "*" } else { " " };
+            let display_index = graphics::set_text_width(format!("{}.",position),5);
+            let mut description = style(format!("{}{}{}",marker,display_index,file_info));
+            if bad_reason.is_some(){
+                description = description.red();
+            }
+            else if is_playing{
+                description = description.green();
+            }
+            else if self.marked.contains(path){
+                description = description.yellow();
+            }
+            let is_highlighted = index+start_index == self.highlighted_index.get();
+            if is_highlighted{
                 description = description.on_blue();
             }
             graphics::draw_text(stdout,description,x,y+index as u16).unwrap();
+            if !self.query.is_empty() && bad_reason.is_none(){
+                let info = file_manager.get_metadata(path).unwrap_or_default();
+                let columns:Vec<(String, usize, usize)> = self.columns.iter().enumerate()
+                    .filter(|(_, column)| column.is_searched())
+                    .map(|(index, column)| (column.text(path, &info, &PlayStats::default()), offsets[index], widths[index]))
+                    .collect();
+                self.draw_matches(stdout, &columns, x+6, y+index as u16, is_highlighted);
+            }
+        }
+        if self.files == 0{
+            graphics::draw_text(stdout,style(String::from("No matches")).dark_grey(),x,y).unwrap();
+        }
+    }
+    // redraws the characters that match the query, columns are (text, offset, width)
+    fn draw_matches(&self, stdout:&mut Stdout, columns:&[(String, usize, usize)], x:u16, y:u16, is_highlighted:bool){
+        for (text, offset, width) in columns.iter(){
+            let indices = match fuzzy_match(&self.query, text){
+                Some(indices) => indices,
+                None => continue,
+            };
+            let chars:Vec<char> = text.chars().collect();
+            let visible = visible_chars(text, *width);
+            for index in indices.into_iter().filter(|index| *index<visible){
+                let column = UnicodeWidthStr::width(chars[..index].iter().collect::<String>().as_str());
+                let mut matched = style(chars[index]).yellow();
+                if is_highlighted{
+                    matched = matched.on_blue();
+                }
+                graphics::draw_text(stdout,matched,x+(offset+column) as u16,y).unwrap();
+            }
         }
 
     }
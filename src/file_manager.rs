@@ -1,8 +1,40 @@
 use std::path::{PathBuf, Path};
-use glob::glob;
-use std::cell::{RefCell};
+use glob::Pattern;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::time::Duration;
+use rand::Rng;
 use rand::seq::SliceRandom;
-use mp3_metadata::MP3Metadata;
+use anyhow::anyhow;
+use crate::library::{Library, Rating, TrackInfo};
+use crate::watcher::LibraryChange;
+use crate::playlist::{Playlist, PlaylistEntry};
+use crate::cue::{self, is_cue, Segment};
+
+#[derive(Clone)]
+pub struct ScanOptions{
+    pub recursive:bool,
+    pub include_hidden:bool,
+    pub ignore_patterns:Vec<Pattern>,
+}
+impl Default for ScanOptions{
+    fn default() -> ScanOptions{
+        ScanOptions{recursive:true,include_hidden:false,ignore_patterns:Vec::new()}
+    }
+}
+impl ScanOptions{
+    pub fn add_ignore_pattern(&mut self,pattern:&str) -> Result<(), glob::PatternError>{
+        self.ignore_patterns.push(Pattern::new(pattern)?);
+        Ok(())
+    }
+    pub fn is_ignored(&self,path:&Path) -> bool{
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        if !self.include_hidden && name.starts_with('.') { return true }
+        self.ignore_patterns.iter().any(|pattern| pattern.matches(name) || pattern.matches_path(path))
+    }
+}
 
 pub struct FileManager{
     pub file_paths: Vec<PathBuf>,
@@ -11,43 +43,347 @@ pub struct FileManager{
     cur: RefCell<usize>,
     size: usize,
     is_shuffled:bool,
+    library:Library,
+    roots:Vec<PathBuf>,
+    options:ScanOptions,
+    // set when the playing file was removed from the list, cur then already points at the track after it
+    current_removed:Cell<bool>,
+    // files that could not be read, with the reason
+    bad_files:HashMap<PathBuf, String>,
+    // what the playlist says about its entries, used where the tags are empty
+    hints:HashMap<PathBuf, TrackInfo>,
+    // played before the rest of the list, next() takes from the front
+    queue:RefCell<VecDeque<PathBuf>>,
+    repeat:RepeatMode,
+    sort_key:SortKey,
+    sort_descending:bool,
+    // position of every file in the loaded playlist, the default order for playlists
+    playlist_order:HashMap<PathBuf, usize>,
+    // the tracks of cue sheets, their virtual paths point into one big audio file
+    segments:HashMap<PathBuf, Segment>,
+}
+
+pub fn is_mp3(path:&Path) -> bool{
+    path.extension().and_then(|ext| ext.to_str()).is_some_and(|ext| ext.eq_ignore_ascii_case("mp3"))
+}
+// visited holds canonical paths of directories and files, so symlink loops
+// and roots that overlap each other are only scanned once
+fn scan_dir(dir:&Path, options:&ScanOptions, visited:&mut HashSet<PathBuf>, file_paths:&mut Vec<PathBuf>){
+    let entries = match fs::read_dir(dir){
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let mut subdirs = Vec::new();
+    for entry in entries.flatten(){
+        let path = entry.path();
+        if options.is_ignored(&path) { continue }
+        let canonical = match path.canonicalize(){
+            Ok(canonical) => canonical,
+            Err(_) => continue,
+        };
+        if canonical.is_dir(){
+            if options.recursive { subdirs.push(path) }
+        }
+        else if (is_mp3(&path) || is_cue(&path)) && visited.insert(canonical){
+            file_paths.push(path);
+        }
+    }
+    for subdir in subdirs{
+        if visited.insert(subdir.canonicalize().unwrap_or_else(|_| subdir.clone())){
+            scan_dir(&subdir, options, visited, file_paths);
+        }
+    }
+}
+#[derive(Clone, Copy, PartialEq)]
+pub enum RepeatMode{
+    Off,
+    One,
+    All,
+}
+impl RepeatMode{
+    pub fn next(self) -> RepeatMode{
+        match self{
+            RepeatMode::Off => RepeatMode::All,
+            RepeatMode::All => RepeatMode::One,
+            RepeatMode::One => RepeatMode::Off,
+        }
+    }
+    pub fn label(self) -> &'static str{
+        match self{
+            // dimmed while off
+            RepeatMode::Off => "REPEAT",
+            RepeatMode::All => "REPEAT ALL",
+            RepeatMode::One => "REPEAT 1",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey{
+    // directory and track number for scanned directories, the file order for playlists
+    Default,
+    Filename,
+    Title,
+    Artist,
+    Album,
+    TrackNumber,
+    Year,
+    Duration,
+    Modified,
+}
+impl SortKey{
+    pub fn next(self) -> SortKey{
+        match self{
+            SortKey::Default => SortKey::Filename,
+            SortKey::Filename => SortKey::Title,
+            SortKey::Title => SortKey::Artist,
+            SortKey::Artist => SortKey::Album,
+            SortKey::Album => SortKey::TrackNumber,
+            SortKey::TrackNumber => SortKey::Year,
+            SortKey::Year => SortKey::Duration,
+            SortKey::Duration => SortKey::Modified,
+            SortKey::Modified => SortKey::Default,
+        }
+    }
+    pub fn label(self) -> &'static str{
+        match self{
+            SortKey::Default => "DEFAULT",
+            SortKey::Filename => "FILENAME",
+            SortKey::Title => "TITLE",
+            SortKey::Artist => "ARTIST",
+            SortKey::Album => "ALBUM",
+            SortKey::TrackNumber => "TRACK NO",
+            SortKey::Year => "YEAR",
+            SortKey::Duration => "DURATION",
+            SortKey::Modified => "MODIFIED",
+        }
+    }
+}
+
+// empty tags go after everything else
+fn compare_text(a:&str, b:&str) -> Ordering{
+    (a.is_empty(), a.to_lowercase()).cmp(&(b.is_empty(), b.to_lowercase()))
+}
+fn compare_numbers(a:Option<u32>, b:Option<u32>) -> Ordering{
+    a.unwrap_or(u32::MAX).cmp(&b.unwrap_or(u32::MAX))
+}
+
+// `path` has to start with `from`
+fn replace_prefix(path:&Path, from:&Path, to:&Path) -> PathBuf{
+    let rest = path.strip_prefix(from).unwrap();
+    if rest.as_os_str().is_empty() { to.to_path_buf() } else { to.join(rest) }
+}
+// directory first, then track number, then file name
+fn compare_paths(a:&Path, b:&Path, library:&Library) -> Ordering{
+    let track_number = |path:&Path| library.get(path).and_then(|info| info.track_number).unwrap_or(u32::MAX);
+    a.parent().cmp(&b.parent())
+        .then_with(|| track_number(a).cmp(&track_number(b)))
+        .then_with(|| a.file_name().cmp(&b.file_name()))
 }
 
 impl FileManager{
-    pub fn new(dir:&Path) -> Result<FileManager,()>{
-        if !dir.is_dir() { return Err(()) }
-        let mut dir_str = String::from(dir.to_str().unwrap());
-        if !dir.ends_with("/") {dir_str+="/"}
-        let format_pattern = dir_str + "*.";
+    pub fn new(dir:&Path) -> Result<FileManager, anyhow::Error>{
+        FileManager::from_roots(&[dir.to_path_buf()], &ScanOptions::default())
+    }
+    pub fn from_roots(roots:&[PathBuf], options:&ScanOptions) -> Result<FileManager, anyhow::Error>{
         let mut file_paths = Vec::new();
-        for entry in glob(format!("{}{}",format_pattern.as_str(),"mp3").as_str()).expect("Failed to read glob pattern"){
-            match entry {
-                Ok(path) =>file_paths.push(path),
-                Err(e) => println!("{:?}", e),
+        let mut visited = HashSet::new();
+        for root in roots{
+            if !root.is_dir() { return Err(anyhow!("{} is not a directory", root.display())) }
+            if visited.insert(root.canonicalize()?){
+                scan_dir(root, options, &mut visited, &mut file_paths);
+            }
+        }
+        let (cue_sheets, mut file_paths):(Vec<PathBuf>, Vec<PathBuf>) = file_paths.into_iter().partition(|path| is_cue(path));
+        let mut library = Library::open();
+        library.update(&file_paths);
+        library.prune();
+        // the index is only a cache, failing to write it just makes the next start slower
+        library.save().ok();
+        let cue_tracks = cue::expand(&cue_sheets, &mut file_paths);
+        file_paths.sort_by(|a, b| compare_paths(a, b, &library));
+        let mut file_manager = FileManager::with_paths(file_paths, library, roots.to_vec(), options.clone())?;
+        for (path, segment, info) in cue_tracks{
+            file_manager.hints.insert(path.clone(), info);
+            file_manager.segments.insert(path, segment);
+        }
+        Ok(file_manager)
+    }
+    // keeps the playlist order, tags are read when a row is first drawn
+    pub fn from_playlist(playlist:&Playlist) -> Result<FileManager, anyhow::Error>{
+        let file_paths = playlist.entries.iter().map(|entry| entry.path.clone()).collect();
+        let mut file_manager = FileManager::with_paths(file_paths, Library::open(), Vec::new(), ScanOptions::default())?;
+        for (order, entry) in playlist.entries.iter().enumerate(){
+            file_manager.playlist_order.entry(entry.path.clone()).or_insert(order);
+            file_manager.hints.insert(entry.path.clone(), TrackInfo{
+                title:entry.title.clone().unwrap_or_default(),
+                artist:entry.artist.clone().unwrap_or_default(),
+                album:entry.album.clone().unwrap_or_default(),
+                duration:entry.duration.unwrap_or_default(),
+                ..TrackInfo::default()
+            });
+            if !entry.path.is_file(){
+                file_manager.mark_bad(&entry.path, &anyhow!("file not found"));
             }
         }
+        Ok(file_manager)
+    }
+    fn with_paths(file_paths:Vec<PathBuf>, library:Library, roots:Vec<PathBuf>, options:ScanOptions) -> Result<FileManager, anyhow::Error>{
         let len = file_paths.len();
         let indexes:Vec<usize> = (0..len).collect();
         if len!=0{
-            Ok(FileManager{file_paths,indexes,cur:RefCell::new(0),size:len,rng:rand::thread_rng(),is_shuffled:false})
+            Ok(FileManager{
+                file_paths,
+                indexes,
+                cur:RefCell::new(0),
+                size:len,
+                rng:rand::thread_rng(),
+                is_shuffled:false,
+                library,
+                roots,
+                options,
+                current_removed:Cell::new(false),
+                bad_files:HashMap::new(),
+                hints:HashMap::new(),
+                queue:RefCell::new(VecDeque::new()),
+                repeat:RepeatMode::Off,
+                sort_key:SortKey::Default,
+                sort_descending:false,
+                playlist_order:HashMap::new(),
+                segments:HashMap::new(),
+            })
         }
         else {
-            println!("\rDirectory is empty");
-            println!("\rChoose directory with mp3 files");
-            Err(())
+            Err(anyhow!("No mp3 files found, choose a directory with mp3 files"))
         }
 
     }
+    // the current order, including shuffling, with what is known about every track.
+    // the tracks of a cue sheet only exist in the player, their audio file is listed once instead
+    pub fn to_playlist(&self) -> Playlist{
+        let mut listed = HashSet::new();
+        let paths = self.get_paths(0, self.size).into_iter()
+            .map(|path| self.audio_file(&path))
+            .filter(|path| listed.insert(path.clone()));
+        let entries = paths.map(|path| {
+            let mut entry = PlaylistEntry::new(path);
+            let info = match (self.library.get(&entry.path), self.hints.get(&entry.path)){
+                (Some(info), Some(hint)) => Some(info.clone().with_fallback(hint)),
+                (info, hint) => info.or(hint).cloned(),
+            };
+            if let Some(info) = info{
+                entry.title = Some(info.title).filter(|title| !title.is_empty());
+                entry.artist = Some(info.artist).filter(|artist| !artist.is_empty());
+                entry.album = Some(info.album).filter(|album| !album.is_empty());
+                entry.duration = Some(info.duration).filter(|duration| *duration != Duration::from_secs(0));
+            }
+            entry
+        }).collect();
+        Playlist::new(entries)
+    }
     pub fn is_shuffled(&self) ->bool{
         self.is_shuffled
     }
     pub fn current_index(&self) -> usize{
         self.indexes[*self.cur.borrow()]
     }
+    pub fn repeat(&self) -> RepeatMode{
+        self.repeat
+    }
+    pub fn cycle_repeat(&mut self){
+        self.repeat = self.repeat.next();
+    }
+    // a folder loaded in place of the list keeps its modes and the queued tracks that can still be played
+    pub fn keep_settings(&mut self, previous:&FileManager){
+        self.repeat = previous.repeat;
+        if previous.sort_order() != self.sort_order() {
+            self.set_sort_order(previous.sort_key, previous.sort_descending);
+        }
+        if previous.is_shuffled {
+            self.make_shuffled(true);
+        }
+        *self.queue.borrow_mut() = previous.queue.borrow().iter()
+            .filter(|queued| queued.is_file() || self.segments.contains_key(*queued))
+            .cloned()
+            .collect();
+        self.current_removed.set(false);
+    }
+    pub fn sort_order(&self) -> (SortKey, bool){
+        (self.sort_key, self.sort_descending)
+    }
+    // re-sorts the list, the current track keeps playing and next/prev follow the new order
+    pub fn set_sort_order(&mut self, key:SortKey, descending:bool){
+        self.sort_key = key;
+        self.sort_descending = descending;
+        // files that were never drawn may not be in the library yet
+        for path in self.file_paths.clone(){
+            self.get_metadata(&path).ok();
+        }
+        let mut order:Vec<usize> = (0..self.size).collect();
+        order.sort_by(|a, b| self.compare(&self.file_paths[*a], &self.file_paths[*b]));
+        let mut new_index = vec![0; self.size];
+        for (new, old) in order.iter().enumerate(){
+            new_index[*old] = new;
+        }
+        self.file_paths = order.iter().map(|old| self.file_paths[*old].clone()).collect();
+        if self.is_shuffled{
+            self.indexes = self.indexes.iter().map(|old| new_index[*old]).collect();
+        }
+        else{
+            let cur = new_index[self.indexes[*self.cur.borrow()]];
+            *self.cur.borrow_mut() = cur;
+            self.indexes = (0..self.size).collect();
+        }
+    }
+    // a field of the indexed tags, or of the playlist entry when the tags lack it, borrowed since sorting asks for every comparison
+    fn known<'a, T>(&'a self, path:&Path, field:impl Fn(&'a TrackInfo) -> Option<T>) -> Option<T>{
+        self.library.get(path).and_then(&field).or_else(|| self.hints.get(path).and_then(&field))
+    }
+    fn compare_default(&self, a:&Path, b:&Path) -> Ordering{
+        if self.playlist_order.is_empty(){
+            return compare_paths(a, b, &self.library)
+        }
+        let order = |path:&Path| self.playlist_order.get(path).copied().unwrap_or(usize::MAX);
+        order(a).cmp(&order(b))
+    }
+    // the chosen sort, ties keep the default order
+    fn compare(&self, a:&Path, b:&Path) -> Ordering{
+        let text = |path, field:fn(&TrackInfo) -> &String| {
+            self.known(path, |info| Some(field(info).as_str()).filter(|text| !text.is_empty())).unwrap_or_default()
+        };
+        let number = |path, field:fn(&TrackInfo) -> Option<u32>| self.known(path, field);
+        let duration = |path| self.known(path, |info| Some(info.duration).filter(|duration| !duration.is_zero())).unwrap_or_default();
+        let ordering = match self.sort_key{
+            SortKey::Default => self.compare_default(a, b),
+            SortKey::Filename => a.file_name().cmp(&b.file_name()),
+            SortKey::Title => compare_text(text(a, |info| &info.title), text(b, |info| &info.title)),
+            SortKey::Artist => compare_text(text(a, |info| &info.artist), text(b, |info| &info.artist)),
+            SortKey::Album => compare_text(text(a, |info| &info.album), text(b, |info| &info.album)),
+            SortKey::TrackNumber => compare_numbers(number(a, |info| info.track_number), number(b, |info| info.track_number)),
+            SortKey::Year => compare_numbers(number(a, |info| info.year), number(b, |info| info.year)),
+            SortKey::Duration => duration(a).cmp(&duration(b)),
+            SortKey::Modified => self.library.modified(a).cmp(&self.library.modified(b)),
+        };
+        let ordering = if self.sort_descending { ordering.reverse() } else { ordering };
+        ordering.then_with(|| self.compare_default(a, b))
+    }
     pub fn toggle_shuffle(&mut self){
         self.is_shuffled = !self.is_shuffled;
         self.make_shuffled(self.is_shuffled);
     }
+    // the shuffled order of an earlier session, files that are new since then play after the others
+    pub fn restore_order(&mut self, order:&[PathBuf]){
+        let positions:HashMap<&Path, usize> = self.file_paths.iter().enumerate().map(|(index, path)| (path.as_path(), index)).collect();
+        let mut is_placed = vec![false; self.size];
+        let mut indexes:Vec<usize> = order.iter()
+            .filter_map(|path| positions.get(path.as_path()).copied())
+            .filter(|index| !std::mem::replace(&mut is_placed[*index], true))
+            .collect();
+        indexes.extend((0..self.size).filter(|index| !is_placed[*index]));
+        self.indexes = indexes;
+        self.is_shuffled = true;
+        self.set_index(0);
+    }
     pub fn make_shuffled(&mut self,shuffle:bool){
         if shuffle{
             self.is_shuffled = true;
@@ -71,39 +407,276 @@ impl FileManager{
         }
         result
     }
-    pub fn get_metadata(&mut self,filename:&Path) -> MP3Metadata{
-
-        mp3_metadata::read_from_file(filename).unwrap()
+    pub fn get_metadata(&mut self,filename:&Path) -> Result<TrackInfo, anyhow::Error>{
+        let result = match self.segments.get(filename){
+            // what the cue sheet does not say comes from the tags of the audio file
+            Some(segment) => self.library.info(&segment.audio).map(|info| {
+                let duration = segment.end.unwrap_or(info.duration).saturating_sub(segment.start);
+                TrackInfo{duration, ..self.hints[filename].clone()}.with_fallback(&info)
+            }),
+            None => self.library.info(filename).map(|info| match self.hints.get(filename){
+                Some(hint) => info.with_fallback(hint),
+                None => info,
+            }),
+        };
+        // a rating given in the player wins over the one in the tag
+        let result = result.map(|info| match self.library.rating(filename){
+            Some(rating) => TrackInfo{rating, ..info},
+            None => info,
+        });
+        if let Err(e) = result.as_ref() {
+            self.mark_bad(filename, e);
+        }
+        result
     }
-    pub fn next(&self) -> Option<PathBuf>{
-        let mut result = None;
-        if *self.cur.try_borrow().unwrap()<self.size-1 {
+    pub fn rating(&mut self,filename:&Path) -> Rating{
+        self.get_metadata(filename).map(|info| info.rating).unwrap_or_default()
+    }
+    pub fn set_rating(&mut self,filename:&Path,rating:Rating){
+        self.library.set_rating(filename, rating);
+    }
+    // an index with ratings that cannot be read, it is left alone until it is fixed
+    pub fn library_error(&self) -> Option<&str>{
+        self.library.error()
+    }
+    // the part of a file a cue track plays, None for whole files
+    pub fn segment(&self,filename:&Path) -> Option<&Segment>{
+        self.segments.get(filename)
+    }
+    // the file that is decoded for a track
+    pub fn audio_file(&self,filename:&Path) -> PathBuf{
+        self.segment(filename).map_or_else(|| filename.to_path_buf(), |segment| segment.audio.clone())
+    }
+    pub fn mark_bad(&mut self,filename:&Path,error:&anyhow::Error){
+        self.bad_files.insert(filename.to_path_buf(), error.to_string());
+    }
+    pub fn is_bad(&self,filename:&Path) -> bool{
+        self.bad_files.contains_key(filename)
+    }
+    pub fn bad_reason(&self,filename:&Path) -> Option<&str>{
+        self.bad_files.get(filename).map(|reason| reason.as_str())
+    }
+    // reads the tags again after they were written, the new mtime makes the library re-read the file
+    pub fn refresh_metadata(&mut self,filename:&Path){
+        self.bad_files.remove(filename);
+        self.library.update(std::slice::from_ref(&filename.to_path_buf()));
+    }
+    pub fn save_library(&mut self) -> Result<(), anyhow::Error>{
+        self.library.save()
+    }
+    // queued tracks come first and do not move the position in the list
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<PathBuf>{
+        let mut result = self.queue.borrow_mut().pop_front();
+        if result.is_some() {
+            return result
+        }
+        if self.current_removed.replace(false) {
+            result = Some(self.get_current());
+        }
+        else if *self.cur.try_borrow().unwrap()<self.size-1 {
             *self.cur.borrow_mut()+=1;
             result = Some(self.get_current());
 
         }
+        else if self.repeat == RepeatMode::All {
+            if self.is_shuffled {
+                self.reshuffle();
+            }
+            *self.cur.borrow_mut() = 0;
+            result = Some(self.get_current());
+        }
         result
 
     }
+    // a new order for the next round of repeat-all, the track that just played does not start it
+    fn reshuffle(&mut self){
+        let last = self.indexes[self.size-1];
+        self.indexes.shuffle(&mut self.rng);
+        if self.size>1 && self.indexes[0] == last {
+            self.indexes.swap(0, self.size-1);
+        }
+    }
     fn get_index(&self,index:usize) -> usize{
         self.indexes[index]
     }
     pub fn get_current(&self) -> PathBuf{
         self.file_paths[self.get_index(*self.cur.try_borrow().unwrap())].clone()
     }
-    pub fn prev(&self) -> Option<PathBuf>{
+    pub fn prev(&mut self) -> Option<PathBuf>{
         let mut result = None;
+        self.current_removed.set(false);
         if *self.cur.try_borrow().unwrap()>0 {
             *self.cur.borrow_mut()-=1;
             result = Some(self.get_current());
         }
+        else if self.repeat == RepeatMode::All {
+            *self.cur.borrow_mut() = self.size-1;
+            result = Some(self.get_current());
+        }
         result
 
     }
     pub fn set_index(&self, new_index:usize){
+        self.current_removed.set(false);
         *self.cur.borrow_mut() = new_index;
     }
+    pub fn position(&self) -> usize{
+        *self.cur.borrow()
+    }
     pub fn tracks_left(&self) -> usize{
-        self.size - 1 - *self.cur.borrow()
+        self.size - 1 - *self.cur.borrow() + self.current_removed.get() as usize + self.queue.borrow().len()
+    }
+    // whether next() has anything to play, with repeat-all it always has
+    pub fn has_next(&self) -> bool{
+        self.tracks_left() != 0 || self.repeat == RepeatMode::All
+    }
+    pub fn position_of(&self, path:&Path) -> Option<usize>{
+        (0..self.size).find(|position| self.file_paths[self.indexes[*position]] == path)
+    }
+    pub fn path_at(&self, position:usize) -> PathBuf{
+        self.file_paths[self.indexes[position]].clone()
+    }
+    pub fn queued(&self) -> Vec<PathBuf>{
+        self.queue.borrow().iter().cloned().collect()
+    }
+    pub fn enqueue(&self, path:PathBuf){
+        self.queue.borrow_mut().push_back(path);
+    }
+    pub fn play_next(&self, path:PathBuf){
+        self.queue.borrow_mut().push_front(path);
+    }
+    pub fn remove_queued(&self, index:usize){
+        self.queue.borrow_mut().remove(index);
+    }
+    // moves the queued track at `index` by `delta` places, returns where it ended up
+    pub fn move_queued(&self, index:usize, delta:i32) -> usize{
+        let mut queue = self.queue.borrow_mut();
+        if index>=queue.len() { return index }
+        let new_index = (index as i32 + delta).clamp(0, queue.len() as i32 - 1) as usize;
+        let path = queue.remove(index).unwrap();
+        queue.insert(new_index, path);
+        new_index
+    }
+    pub fn roots(&self) -> &[PathBuf]{
+        &self.roots
+    }
+    // returns true if the list of files changed
+    pub fn apply_change(&mut self, change:&LibraryChange) -> bool{
+        match change{
+            LibraryChange::Added(path) => self.add_path(path),
+            LibraryChange::Removed(path) => self.remove_path(path),
+            LibraryChange::Renamed(from, to) => self.rename_path(from, to),
+            LibraryChange::Modified(path) => {
+                // the file may have been fixed, it is tried again the next time it is read
+                self.bad_files.remove(path);
+                self.library.update(std::slice::from_ref(path));
+                false
+            }
+        }
+    }
+    fn is_excluded(&self, path:&Path) -> bool{
+        match self.roots.iter().find(|root| path.starts_with(root)){
+            Some(root) => path.ancestors()
+                .take_while(|ancestor| ancestor != root)
+                .any(|ancestor| self.options.is_ignored(ancestor)),
+            None => true,
+        }
+    }
+    fn add_path(&mut self, path:&Path) -> bool{
+        if self.is_excluded(path) || self.file_paths.iter().any(|known| known == path) { return false }
+        let mut added = Vec::new();
+        if path.is_dir(){
+            let mut visited = self.file_paths.iter().filter_map(|known| known.canonicalize().ok()).collect();
+            scan_dir(path, &self.options, &mut visited, &mut added);
+            // new cue sheets are read when the folder is loaded again
+            added.retain(|path| is_mp3(path));
+        }
+        else if is_mp3(path){
+            added.push(path.to_path_buf());
+        }
+        self.library.update(&added);
+        for path in added.iter(){
+            self.insert_file(path.clone());
+        }
+        !added.is_empty()
+    }
+    fn insert_file(&mut self, path:PathBuf){
+        let index = self.file_paths.partition_point(|known| self.compare(known, &path) == Ordering::Less);
+        self.file_paths.insert(index, path);
+        for known in self.indexes.iter_mut(){
+            if *known>=index { *known+=1 }
+        }
+        let cur = *self.cur.borrow();
+        if self.is_shuffled{
+            // new files are played somewhere after the current track
+            let order = self.rng.gen_range(cur+1, self.size+1);
+            self.indexes.insert(order, index);
+        }
+        else{
+            self.indexes.insert(index, index);
+            if index<=cur { *self.cur.borrow_mut()+=1 }
+        }
+        self.size+=1;
+    }
+    fn remove_path(&mut self, path:&Path) -> bool{
+        // a removed directory takes all files below it
+        self.queue.borrow_mut().retain(|queued| !queued.starts_with(path));
+        let removed:Vec<usize> = (0..self.size).rev().filter(|index| self.file_paths[*index].starts_with(path)).collect();
+        let mut changed = false;
+        for index in removed{
+            changed |= self.remove_file(index);
+        }
+        changed
+    }
+    fn remove_file(&mut self, index:usize) -> bool{
+        // the list is never empty, the last file stays until something else is added
+        if self.size == 1 { return false }
+        let path = self.file_paths.remove(index);
+        self.bad_files.remove(&path);
+        let order = self.indexes.iter().position(|known| *known == index).unwrap();
+        self.indexes.remove(order);
+        for known in self.indexes.iter_mut(){
+            if *known>index { *known-=1 }
+        }
+        self.size-=1;
+        let cur = *self.cur.borrow();
+        if order<cur {
+            *self.cur.borrow_mut()-=1;
+        }
+        else if order == cur {
+            // the playing track goes on from its open file, next() then continues with the track that followed it
+            if cur == self.size {
+                *self.cur.borrow_mut()-=1;
+                self.current_removed.set(false);
+            }
+            else {
+                self.current_removed.set(true);
+            }
+        }
+        true
+    }
+    // renamed files keep their place so the current track and shuffle order stay as they are
+    fn rename_path(&mut self, from:&Path, to:&Path) -> bool{
+        // ratings cannot be read again from the files like the tags, so they are written right away.
+        // an index that cannot be written also fails the next rating, which reports it
+        if self.library.rename_ratings(from, to) {
+            self.library.save().ok();
+        }
+        if self.is_excluded(to) || (!to.is_dir() && !is_mp3(to)){
+            return self.remove_path(from)
+        }
+        let renamed:Vec<usize> = (0..self.size).filter(|index| self.file_paths[*index].starts_with(from)).collect();
+        if renamed.is_empty(){
+            return self.add_path(to)
+        }
+        for index in renamed.iter(){
+            self.file_paths[*index] = replace_prefix(&self.file_paths[*index], from, to);
+        }
+        for queued in self.queue.borrow_mut().iter_mut().filter(|queued| queued.starts_with(from)){
+            *queued = replace_prefix(queued, from, to);
+        }
+        self.library.update(&renamed.iter().map(|index| self.file_paths[*index].clone()).collect::<Vec<_>>());
+        true
     }
 }
@@ -2,24 +2,35 @@ extern crate unicode_width;
 use unicode_width::UnicodeWidthStr;
 
 use crossterm::{QueueableCommand, cursor,Result,style};
+use crossterm::style::Colorize;
 use std::io::{Stdout};
 use std::fmt::Display;
 use std::cell::Cell;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use crate::audio_controller::Levels;
 
 pub mod graphic_symbols {
-    pub const DOUBLE_TOP_LEFT_CORNER: &'static str = "╔";
-    pub const DOUBLE_TOP_RIGHT_CORNER: &'static str = "╗";
-    pub const DOUBLE_BOTTOM_LEFT_CORNER: &'static str = "╚";
-    pub const DOUBLE_BOTTOM_RIGHT_CORNER: &'static str = "╝";
-    pub const DOUBLE_VERTICAL_LINE: &'static str = "║";
-    pub const DOUBLE_HORIZONTAL_LINE: &'static str = "═";
-    pub const CELL: &'static str = "■";
-    pub const DOUBLE_LINE_VERTICAL_AND_LEFT:&'static str = "╣";
-    pub const SINGLE_HORIZONTAL_LINE:&'static str = "─";
-    pub const PAUSE:&'static str = "PAUSE";
-    pub const PLAY:&'static str = "PLAY";
-    pub const SHUFFLE:&'static str = "🔀️🔀️🔀️🔀️";
+    pub const DOUBLE_TOP_LEFT_CORNER: &str = "╔";
+    pub const DOUBLE_TOP_RIGHT_CORNER: &str = "╗";
+    pub const DOUBLE_BOTTOM_LEFT_CORNER: &str = "╚";
+    pub const DOUBLE_BOTTOM_RIGHT_CORNER: &str = "╝";
+    pub const DOUBLE_VERTICAL_LINE: &str = "║";
+    pub const DOUBLE_HORIZONTAL_LINE: &str = "═";
+    pub const CELL: &str = "■";
+    pub const DOUBLE_LINE_VERTICAL_AND_LEFT:&str = "╣";
+    pub const SINGLE_HORIZONTAL_LINE:&str = "─";
+    pub const PAUSE:&str = "PAUSE";
+    pub const PLAY:&str = "PLAY";
+    pub const SHUFFLE:&str = "🔀️🔀️🔀️🔀️";
+    pub const PEAK_HOLD:&str = "|";
+    pub const CLIP:&str = "CLIP";
+    pub const NOW_PLAYING:&str = "▶";
+    pub const SORT_ASCENDING:&str = "▲";
+    pub const SORT_DESCENDING:&str = "▼";
+    pub const CHAPTER_MARKER:&str = "┼";
+    pub const STAR:&str = "★";
+    pub const EMPTY_STAR:&str = "☆";
+    pub const FAVOURITE:&str = "♥";
 
 }
 
@@ -96,11 +107,16 @@ impl Drawable for Square{
 
 pub struct Slider{
     length:u16,
-    pos:Cell<f32>
+    pos:Cell<f32>,
+    // positions between 0 and 1 marked on the line, the cell is drawn over them
+    markers:Vec<f32>,
 }
 impl Slider{
     pub fn new(length:u16) -> Slider{
-        Slider{length,pos:Cell::new(0.0)}
+        Slider{length,pos:Cell::new(0.0),markers:Vec::new()}
+    }
+    pub fn set_markers(&mut self,markers:Vec<f32>){
+        self.markers = markers;
     }
     pub fn length(&self) -> u16{
         self.length
@@ -113,6 +129,9 @@ impl Slider{
 impl Drawable for Slider{
     fn draw(&self,stdout:&mut Stdout,x:u16,y:u16) -> Result<()> {
         draw_text(stdout, graphic_symbols::SINGLE_HORIZONTAL_LINE.repeat(self.length as usize).as_str(), x, y).unwrap();
+        for marker in self.markers.iter().filter(|marker| (0.0..1.0).contains(*marker)){
+            draw_text(stdout, graphic_symbols::CHAPTER_MARKER, x + (marker * self.length as f32) as u16, y).unwrap();
+        }
         let cell_x = (self.pos.get() * self.length as f32) as u16;
         draw_text(stdout, graphic_symbols::CELL, x + cell_x, y).unwrap();
         Ok(())
@@ -144,14 +163,32 @@ impl Drawable for VolumeDisplay{
 pub struct TimeSlider{
     pub slider:Slider,
     duration: Option<Duration>,
-    timestamp:Duration
+    timestamp:Duration,
+    markers:Vec<Duration>,
 }
 impl TimeSlider{
     pub fn new(slider_length:u16) -> TimeSlider{
-        TimeSlider{slider:Slider::new(slider_length),duration:None,timestamp:Duration::from_secs(0)}
+        TimeSlider{slider:Slider::new(slider_length),duration:None,timestamp:Duration::from_secs(0),markers:Vec::new()}
     }
     pub fn set_duration(&mut self,duration:Option<Duration>){
-        self.duration = duration;
+        if self.duration != duration{
+            self.duration = duration;
+            self.update_markers();
+        }
+    }
+    // chapter starts, the first one is usually at zero and left out
+    pub fn set_markers(&mut self,markers:Vec<Duration>){
+        self.markers = markers;
+        self.update_markers();
+    }
+    fn update_markers(&mut self){
+        let positions = match self.duration{
+            Some(duration) if !duration.is_zero() => self.markers.iter()
+                .filter(|marker| !marker.is_zero())
+                .map(|marker| marker.as_secs_f32()/duration.as_secs_f32()).collect(),
+            _ => Vec::new(),
+        };
+        self.slider.set_markers(positions);
     }
     pub fn set_timestamp(&mut self,timestamp:Duration){
         self.timestamp = timestamp
@@ -176,6 +213,92 @@ impl Drawable for TimeSlider{
     }
 }
 
+// meter scale in dBFS, 0 dB is the clipping point of the output
+const METER_FLOOR_DB:f32 = -40.0;
+const METER_CEILING_DB:f32 = 3.0;
+const PEAK_HOLD_TIME:Duration = Duration::from_millis(1500);
+
+fn amplitude_to_db(amplitude:f32) -> f32{
+    20.0*amplitude.log10()
+}
+pub struct LevelMeter{
+    length:u16,
+    levels:Levels,
+    peak_hold:[f32;2],
+    hold_since:[Instant;2],
+}
+impl LevelMeter{
+    pub fn new(length:u16) -> LevelMeter{
+        LevelMeter{length,levels:Levels::default(),peak_hold:[0.0;2],hold_since:[Instant::now();2]}
+    }
+    pub fn set_levels(&mut self,levels:Levels){
+        let now = Instant::now();
+        for (channel, peak) in levels.peak.iter().enumerate(){
+            if *peak>=self.peak_hold[channel] || now.duration_since(self.hold_since[channel])>PEAK_HOLD_TIME{
+                self.peak_hold[channel] = *peak;
+                self.hold_since[channel] = now;
+            }
+        }
+        self.levels = levels;
+    }
+    fn cells(&self,amplitude:f32) -> u16{
+        let pos = (amplitude_to_db(amplitude)-METER_FLOOR_DB)/(METER_CEILING_DB-METER_FLOOR_DB);
+        (pos.clamp(0.0, 1.0)*self.length as f32) as u16
+    }
+}
+impl Drawable for LevelMeter{
+    fn draw(&self,stdout:&mut Stdout,mut x:u16,y:u16) -> Result<()>{
+        draw_text(stdout,"L",x,y)?;
+        draw_text(stdout,"R",x,y+1)?;
+        x+=2;
+        for channel in 0..2{
+            let row = y+channel as u16;
+            let rms = self.cells(self.levels.rms[channel]);
+            let peak = self.cells(self.levels.peak[channel]).max(rms);
+            draw_text(stdout,style::style(graphic_symbols::CELL.repeat(rms as usize)).green(),x,row)?;
+            draw_text(stdout,style::style(graphic_symbols::CELL.repeat((peak-rms) as usize)).dark_green(),x+rms,row)?;
+            draw_text(stdout,style::style(graphic_symbols::SINGLE_HORIZONTAL_LINE.repeat((self.length-peak) as usize)).dark_grey(),x+peak,row)?;
+
+            let hold = self.peak_hold[channel];
+            let clipped = hold>1.0;
+            let hold_x = x+self.cells(hold).min(self.length-1);
+            let hold_marker = if clipped { style::style(graphic_symbols::PEAK_HOLD).red() } else { style::style(graphic_symbols::PEAK_HOLD).yellow() };
+            if hold>0.0 { draw_text(stdout,hold_marker,hold_x,row)?; }
+
+            let db_text = if hold>0.0 { format!("{:>6.1}dB",amplitude_to_db(hold)) } else { String::from("  -inf dB") };
+            draw_text(stdout,db_text,x+self.length+1,row)?;
+            if clipped{
+                draw_text(stdout,style::style(graphic_symbols::CLIP).red(),x+self.length+11,row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+const STATUS_MESSAGE_TIME:Duration = Duration::from_secs(5);
+
+pub struct StatusLine{
+    width:u16,
+    message:Option<(String, Instant)>,
+}
+impl StatusLine{
+    pub fn new(width:u16) -> StatusLine{
+        StatusLine{width,message:None}
+    }
+    pub fn set_message(&mut self,message:String){
+        self.message = Some((message, Instant::now()));
+    }
+}
+impl Drawable for StatusLine{
+    fn draw(&self,stdout:&mut Stdout,x:u16,y:u16) -> Result<()>{
+        if let Some((message, since)) = self.message.as_ref(){
+            if since.elapsed()<STATUS_MESSAGE_TIME{
+                draw_text(stdout,style::style(set_text_width(message.clone(),self.width as usize)).yellow(),x,y)?;
+            }
+        }
+        Ok(())
+    }
+}
 
 pub fn draw_text<T:Display+Clone>(stdout:&mut Stdout,text:T,x:u16,y:u16) -> Result<()>{
     stdout
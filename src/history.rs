@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use crate::bookmarks::seconds;
+use crate::library::TrackInfo;
+
+const HISTORY_FILE:&str = "history.jsonl";
+// a play counts once half of the track or four minutes of it were heard, the rule scrobblers use
+const COUNTED_PLAY:Duration = Duration::from_secs(240);
+
+pub fn default_location() -> PathBuf{
+    dirs::data_dir().map(|dir| dir.join("rplayer")).unwrap_or_default().join(HISTORY_FILE)
+}
+
+// negative before 1970, a clock set that far back still gives a time that reads back
+fn unix_seconds(time:SystemTime) -> i64{
+    match time.duration_since(UNIX_EPOCH){
+        Ok(since) => since.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+// times are stored as seconds since 1970
+mod unix_time{
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use serde::{Deserialize, Deserializer, Serializer};
+    pub fn serialize<S:Serializer>(time:&SystemTime, serializer:S) -> Result<S::Ok, S::Error>{
+        serializer.serialize_i64(super::unix_seconds(*time))
+    }
+    pub fn deserialize<'de, D:Deserializer<'de>>(deserializer:D) -> Result<SystemTime, D::Error>{
+        let seconds = i64::deserialize(deserializer)?;
+        let since = Duration::from_secs(seconds.unsigned_abs());
+        let time = if seconds<0 { UNIX_EPOCH.checked_sub(since) } else { UNIX_EPOCH.checked_add(since) };
+        time.ok_or_else(|| serde::de::Error::custom(format!("{} seconds is out of range", seconds)))
+    }
+}
+
+// (year, month, day) of a day counted from 1970-01-01, Howard Hinnant's civil_from_days
+fn civil_from_days(days:i64) -> (i64, u32, u32){
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+    let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+    let month = (5*day_of_year + 2) / 153;
+    let day = day_of_year - (153*month + 2)/5 + 1;
+    let month = if month<10 { month+3 } else { month-9 };
+    (year_of_era + era*400 + (month<=2) as i64, month as u32, day as u32)
+}
+
+// "2026-10-19T14:03:00Z", for the exports
+pub fn format_utc(time:SystemTime) -> String{
+    let seconds = unix_seconds(time);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let rest = seconds.rem_euclid(86400);
+    format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, rest/3600, rest%3600/60, rest%60)
+}
+
+// "2026-10-19 14:03" in the local time zone
+#[cfg(unix)]
+pub fn format_local(time:SystemTime) -> String{
+    let seconds = unix_seconds(time) as libc::time_t;
+    let mut local:libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&seconds, &mut local) }.is_null() {
+        return format_utc(time)
+    }
+    format!("{}-{:02}-{:02} {:02}:{:02}", local.tm_year+1900, local.tm_mon+1, local.tm_mday, local.tm_hour, local.tm_min)
+}
+#[cfg(not(unix))]
+pub fn format_local(time:SystemTime) -> String{
+    format_utc(time)
+}
+
+// one track from the moment it started until the next one did
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Play{
+    pub path:PathBuf,
+    pub title:String,
+    pub artist:String,
+    pub album:String,
+    #[serde(with = "unix_time")]
+    pub started:SystemTime,
+    #[serde(with = "unix_time")]
+    pub ended:SystemTime,
+    // the time it was actually heard, without pauses and the parts skipped by seeking
+    #[serde(with = "seconds")]
+    pub played:Duration,
+    #[serde(with = "seconds")]
+    pub duration:Duration,
+    // ran to its end instead of being skipped or stopped
+    pub finished:bool,
+}
+impl Play{
+    pub fn is_counted(&self) -> bool{
+        self.finished || self.played>=COUNTED_PLAY || (!self.duration.is_zero() && self.played*2>=self.duration)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PlayStats{
+    pub count:u32,
+    pub last_played:Option<SystemTime>,
+}
+
+// the columns of the exports
+#[derive(Serialize)]
+struct ExportedPlay<'a>{
+    path:&'a Path,
+    title:&'a str,
+    artist:&'a str,
+    album:&'a str,
+    started:String,
+    ended:String,
+    played:f64,
+    duration:f64,
+    finished:bool,
+    counted:bool,
+}
+impl<'a> ExportedPlay<'a>{
+    fn new(play:&'a Play) -> ExportedPlay<'a>{
+        ExportedPlay{
+            path:&play.path,
+            title:&play.title,
+            artist:&play.artist,
+            album:&play.album,
+            started:format_utc(play.started),
+            ended:format_utc(play.ended),
+            played:play.played.as_secs_f64(),
+            duration:play.duration.as_secs_f64(),
+            finished:play.finished,
+            counted:play.is_counted(),
+        }
+    }
+}
+
+// fields with separators, quotes or line breaks are quoted
+fn csv_field(text:&str) -> String{
+    match text.contains([',', '"', '\n', '\r']){
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+// every play, one JSON object a line so recording only appends
+pub struct History{
+    location:PathBuf,
+    // oldest first
+    plays:Vec<Play>,
+    // by absolute path, only counted plays
+    stats:HashMap<PathBuf, PlayStats>,
+}
+impl History{
+    // a missing file is an empty history, lines that do not parse are skipped, e.g. one cut off by a crash
+    pub fn open(location:&Path) -> History{
+        let mut history = History{location:location.to_path_buf(),plays:Vec::new(),stats:HashMap::new()};
+        if let Ok(file) = File::open(location) {
+            for line in BufReader::new(file).lines().map_while(Result::ok){
+                if let Ok(play) = serde_json::from_str::<Play>(&line) {
+                    history.add(play);
+                }
+            }
+        }
+        history
+    }
+    fn add(&mut self, play:Play){
+        if play.is_counted() {
+            let stats = self.stats.entry(play.path.clone()).or_default();
+            stats.count += 1;
+            stats.last_played = stats.last_played.max(Some(play.started));
+        }
+        self.plays.push(play);
+    }
+    pub fn location(&self) -> &Path{
+        &self.location
+    }
+    pub fn plays(&self) -> &[Play]{
+        &self.plays
+    }
+    // the list has the paths the way they were given, the history absolute ones
+    pub fn stats(&self, path:&Path) -> PlayStats{
+        let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        self.stats.get(&path).copied().unwrap_or_default()
+    }
+    pub fn record(&mut self, mut play:Play) -> Result<(), anyhow::Error>{
+        play.path = std::path::absolute(&play.path)?;
+        if let Some(dir) = self.location.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.location)?;
+        file.write_all((serde_json::to_string(&play)? + "\n").as_bytes())?;
+        self.add(play);
+        Ok(())
+    }
+    pub fn write_csv(&self, mut writer:impl Write) -> Result<(), anyhow::Error>{
+        writeln!(writer, "path,title,artist,album,started,ended,played,duration,finished,counted")?;
+        for play in self.plays.iter().map(ExportedPlay::new){
+            writeln!(writer, "{},{},{},{},{},{},{:.1},{:.1},{},{}",
+                     csv_field(&play.path.to_string_lossy()),
+                     csv_field(play.title),
+                     csv_field(play.artist),
+                     csv_field(play.album),
+                     play.started,
+                     play.ended,
+                     play.played,
+                     play.duration,
+                     play.finished,
+                     play.counted)?;
+        }
+        Ok(())
+    }
+    pub fn write_json(&self, mut writer:impl Write) -> Result<(), anyhow::Error>{
+        let plays:Vec<ExportedPlay> = self.plays.iter().map(ExportedPlay::new).collect();
+        serde_json::to_writer_pretty(&mut writer, &plays)?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+// follows the playing track, every track that started becomes a Play once the next one starts
+pub struct Recorder{
+    // AudioPlayer::plays_started of the current play, repeat-one starts the same track again
+    serial:Option<u64>,
+    current:Option<Play>,
+    last_tick:Instant,
+}
+impl Default for Recorder{
+    fn default() -> Recorder{
+        Recorder{serial:None,current:None,last_tick:Instant::now()}
+    }
+}
+impl Recorder{
+    pub fn is_new(&self, serial:u64) -> bool{
+        self.serial != Some(serial)
+    }
+    // returns the play that was going on
+    pub fn start(&mut self, serial:u64, path:PathBuf, info:&TrackInfo) -> Option<Play>{
+        let previous = self.finish(false);
+        self.serial = Some(serial);
+        self.current = Some(Play{
+            path,
+            title:info.title.clone(),
+            artist:info.artist.clone(),
+            album:info.album.clone(),
+            started:SystemTime::now(),
+            ended:SystemTime::now(),
+            played:Duration::from_secs(0),
+            duration:info.duration,
+            finished:false,
+        });
+        previous
+    }
+    // called every frame, the time in between counts while the track is heard
+    pub fn tick(&mut self, is_playing:bool){
+        let now = Instant::now();
+        if let Some(play) = self.current.as_mut().filter(|_| is_playing) {
+            // a resumed track only starts once it is actually played
+            if play.played.is_zero() {
+                play.started = SystemTime::now();
+            }
+            play.played += now - self.last_tick;
+        }
+        self.last_tick = now;
+    }
+    pub fn finish(&mut self, finished:bool) -> Option<Play>{
+        let mut play = self.current.take()?;
+        play.ended = SystemTime::now();
+        play.finished = finished;
+        Some(play)
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn civil_from_days_dates(){
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(civil_from_days(20745), (2026, 10, 19));
+    }
+
+    #[test]
+    fn format_utc_times(){
+        assert_eq!(format_utc(UNIX_EPOCH + Duration::from_secs(1792418580)), "2026-10-19T14:03:00Z");
+    }
+
+    #[test]
+    fn times_before_1970_read_back(){
+        #[derive(Serialize, Deserialize)]
+        struct Time(#[serde(with = "unix_time")] SystemTime);
+        for time in [UNIX_EPOCH - Duration::from_secs(86400), UNIX_EPOCH + Duration::from_secs(1792418580)]{
+            let text = serde_json::to_string(&Time(time)).unwrap();
+            assert_eq!(serde_json::from_str::<Time>(&text).unwrap().0, time);
+        }
+        assert_eq!(serde_json::to_string(&Time(UNIX_EPOCH - Duration::from_secs(86400))).unwrap(), "-86400");
+        assert_eq!(format_utc(UNIX_EPOCH - Duration::from_secs(1)), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed(){
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("one, two"), "\"one, two\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,83 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::history::{self, History, Play};
+
+// the recently played tracks, newest first
+pub struct HistoryView{
+    history:History,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl HistoryView{
+    pub fn new(history:History, viewport_size:usize) -> HistoryView{
+        HistoryView{history,viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    pub fn history(&self) -> &History{
+        &self.history
+    }
+    // the new play goes on top, the selection stays on the play it was on
+    pub fn record(&mut self, play:Play) -> Result<(), anyhow::Error>{
+        self.history.record(play)?;
+        if self.selected_index.get() != 0 {
+            self.select(self.selected_index.get()+1);
+        }
+        Ok(())
+    }
+    pub fn selected(&self) -> Option<&Play>{
+        self.history.plays().iter().rev().nth(self.selected_index.get())
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.history.plays().len().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let column_width = relative_size(1.0/4.0,width) as usize;
+        let row_width = column_width*4+3;
+        let header = format!("Recently played ({}), Enter plays the track again", self.history.plays().len());
+        graphics::draw_text(stdout,style(graphics::set_text_width(header, row_width)).on_dark_blue(),x,y).unwrap();
+        y+=1;
+        if self.history.plays().is_empty(){
+            let empty = style(format!("Nothing played yet, plays are recorded in {}", self.history.location().display())).dark_grey();
+            graphics::draw_text(stdout,empty,x,y).unwrap();
+            return
+        }
+        let start_index = self.start_index.get();
+        for (index,play) in self.history.plays().iter().rev().enumerate().skip(start_index).take(self.viewport_size){
+            let name = match play.title.is_empty(){
+                true => play.path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                false if play.artist.is_empty() => play.title.clone(),
+                false => format!("{} - {}", play.artist, play.title),
+            };
+            let text = format!("{} {:>9} / {:<9} {}",
+                               history::format_local(play.started),
+                               graphics::duration_to_mmss(play.played),
+                               graphics::duration_to_mmss(play.duration),
+                               name);
+            let mut description = style(graphics::set_text_width(text, row_width));
+            // skipped before it counted as a play
+            if !play.is_counted(){
+                description = description.dark_grey();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
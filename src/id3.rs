@@ -0,0 +1,761 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::Duration;
+use anyhow::anyhow;
+
+// id3v2.2 uses three letter frame ids, they are stored under their v2.3/v2.4 names
+const V22_FRAME_IDS:[(&str, &str); 13] = [
+    ("TT2", "TIT2"), ("TP1", "TPE1"), ("TP2", "TPE2"), ("TAL", "TALB"), ("TRK", "TRCK"),
+    ("TYE", "TYER"), ("TCO", "TCON"), ("COM", "COMM"), ("PIC", "APIC"), ("ULT", "USLT"),
+    ("SLT", "SYLT"), ("TPA", "TPOS"), ("TLE", "TLEN"),
+];
+
+fn syncsafe(bytes:&[u8]) -> usize{
+    bytes.iter().fold(0, |size, byte| (size << 7) | (*byte & 0x7f) as usize)
+}
+fn to_syncsafe(size:usize) -> [u8; 4]{
+    [(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f, (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]
+}
+fn big_endian(bytes:&[u8]) -> usize{
+    bytes.iter().fold(0, |size, byte| (size << 8) | *byte as usize)
+}
+// unsynchronisation inserts a zero after every 0xff
+fn resync(bytes:&[u8]) -> Vec<u8>{
+    let mut result = Vec::with_capacity(bytes.len());
+    for (index, byte) in bytes.iter().enumerate(){
+        if *byte == 0 && index>0 && bytes[index-1] == 0xff { continue }
+        result.push(*byte);
+    }
+    result
+}
+
+// text encodings of id3v2: 0 latin-1, 1 utf-16 with bom, 2 utf-16be, 3 utf-8
+pub fn decode_text(encoding:u8, bytes:&[u8]) -> String{
+    match encoding{
+        1 | 2 => {
+            let (big_endian, bytes) = match bytes{
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                _ => (encoding == 2, bytes),
+            };
+            let units:Vec<u16> = bytes.chunks_exact(2)
+                .map(|pair| if big_endian { u16::from_be_bytes([pair[0], pair[1]]) } else { u16::from_le_bytes([pair[0], pair[1]]) })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        3 => String::from_utf8_lossy(bytes).into_owned(),
+        _ => bytes.iter().map(|byte| *byte as char).collect(),
+    }
+}
+// splits off one terminated string, the terminator is two zero bytes in utf-16
+pub fn split_terminated(encoding:u8, bytes:&[u8]) -> (String, &[u8]){
+    let end = if encoding == 1 || encoding == 2 {
+        bytes.chunks_exact(2).position(|pair| pair == [0, 0]).map(|index| (index*2, index*2+2))
+    } else {
+        bytes.iter().position(|byte| *byte == 0).map(|index| (index, index+1))
+    };
+    match end{
+        Some((end, rest)) => (decode_text(encoding, &bytes[..end]), &bytes[rest..]),
+        None => (decode_text(encoding, bytes), &[]),
+    }
+}
+
+pub struct Frame{
+    pub id:String,
+    pub data:Vec<u8>,
+}
+
+pub struct Chapter{
+    pub start:Duration,
+    pub end:Duration,
+    pub title:String,
+}
+
+pub struct Tag{
+    // 2, 3 or 4
+    pub version:u8,
+    pub frames:Vec<Frame>,
+    // ids of the compressed and encrypted frames, they cannot be read and would be lost when the tag is written
+    unreadable:Vec<String>,
+}
+impl Tag{
+    // None when the file does not start with an id3v2 tag
+    pub fn read(path:&Path) -> Result<Option<Tag>, anyhow::Error>{
+        let mut file = File::open(path)?;
+        let mut header = [0; 10];
+        if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" { return Ok(None) }
+        let mut body = vec![0; syncsafe(&header[6..10])];
+        file.read_exact(&mut body)?;
+        Ok(Tag::parse(header[3], header[5], body))
+    }
+    // the tag at the start of `bytes` and where the audio after it starts
+    fn from_bytes(bytes:&[u8]) -> (Option<Tag>, usize){
+        if bytes.len()<10 || &bytes[..3] != b"ID3" { return (None, 0) }
+        let size = syncsafe(&bytes[6..10]);
+        // v2.4 tags can end with a copy of the header
+        let footer = if bytes[3] == 4 && bytes[5] & 0x10 != 0 { 10 } else { 0 };
+        let end = (10+size).min(bytes.len());
+        (Tag::parse(bytes[3], bytes[5], bytes[10..end].to_vec()), (end+footer).min(bytes.len()))
+    }
+    fn parse(version:u8, flags:u8, mut body:Vec<u8>) -> Option<Tag>{
+        if !(2..=4).contains(&version) { return None }
+        // v2.2 used this flag for a compression that was never specified
+        if version == 2 && flags & 0x40 != 0 { return None }
+        if version<4 && flags & 0x80 != 0 { body = resync(&body) }
+        let mut offset = 0;
+        if version>2 && flags & 0x40 != 0 && body.len()>=4 {
+            offset = match version{
+                3 => big_endian(&body[..4]) + 4,
+                _ => syncsafe(&body[..4]),
+            };
+        }
+        let (frames, unreadable) = Tag::parse_frames(version, flags, &body[offset.min(body.len())..]);
+        Some(Tag{version,frames,unreadable})
+    }
+    // CHAP and CTOC frames contain frames of their own in the same format
+    fn parse_frames(version:u8, flags:u8, body:&[u8]) -> (Vec<Frame>, Vec<String>){
+        let (id_length, header_length) = if version == 2 { (3, 6) } else { (4, 10) };
+        let mut frames = Vec::new();
+        let mut unreadable = Vec::new();
+        let mut offset = 0;
+        while offset+header_length<=body.len(){
+            let header = &body[offset..offset+header_length];
+            // the rest is padding
+            if header[0] == 0 { break }
+            let id = String::from_utf8_lossy(&header[..id_length]).into_owned();
+            let size = match version{
+                2 => big_endian(&header[3..6]),
+                3 => big_endian(&header[4..8]),
+                _ => syncsafe(&header[4..8]),
+            };
+            let start = offset+header_length;
+            let end = (start+size).min(body.len());
+            offset = start+size;
+            let mut data = &body[start..end];
+            let format_flags = if version == 2 { 0 } else { header[9] };
+            let frame = match version{
+                2 => Some(data.to_vec()),
+                // compressed and encrypted frames are not read, a group id byte is dropped
+                3 if format_flags & 0xc0 != 0 => None,
+                3 => {
+                    if format_flags & 0x20 != 0 && !data.is_empty() { data = &data[1..] }
+                    Some(data.to_vec())
+                }
+                _ if format_flags & 0x0c != 0 => None,
+                _ => {
+                    if format_flags & 0x40 != 0 && !data.is_empty() { data = &data[1..] }
+                    if format_flags & 0x01 != 0 && data.len()>=4 { data = &data[4..] }
+                    Some(if format_flags & 0x02 != 0 || flags & 0x80 != 0 { resync(data) } else { data.to_vec() })
+                }
+            };
+            match frame{
+                Some(data) => {
+                    let id = match V22_FRAME_IDS.iter().find(|(old, _)| *old == id){
+                        Some((_, new)) if version == 2 => new.to_string(),
+                        _ => id,
+                    };
+                    frames.push(Frame{id,data});
+                }
+                None => unreadable.push(id),
+            }
+        }
+        (frames, unreadable)
+    }
+    pub fn frames<'a>(&'a self, id:&'a str) -> impl Iterator<Item=&'a Frame> + 'a{
+        self.frames.iter().filter(move |frame| frame.id == id)
+    }
+    // the value of a text frame, v2.4 separates several values with zeros
+    pub fn text(&self, id:&str) -> Option<String>{
+        let frame = self.frames(id).next()?;
+        let (encoding, bytes) = frame.data.split_first()?;
+        let mut values = Vec::new();
+        let mut rest = bytes;
+        while !rest.is_empty(){
+            let (value, remaining) = split_terminated(*encoding, rest);
+            if !value.is_empty() { values.push(value) }
+            rest = remaining;
+        }
+        Some(values.join("; ")).filter(|text| !text.is_empty())
+    }
+    // APIC: encoding, mime type, picture type, description, data; the front cover is preferred.
+    // v2.2 has a three letter image format instead of the mime type
+    pub fn picture(&self) -> Option<Vec<u8>>{
+        let mut pictures:Vec<(u8, &[u8])> = self.frames("APIC").filter_map(|frame| {
+            let (encoding, bytes) = frame.data.split_first()?;
+            let (mime, bytes) = match self.version{
+                2 => (String::new(), bytes.get(3..)?),
+                _ => split_terminated(0, bytes),
+            };
+            // "-->" links to the picture instead of containing it
+            if mime == "-->" { return None }
+            let (picture_type, bytes) = bytes.split_first()?;
+            let (_, data) = split_terminated(*encoding, bytes);
+            Some((*picture_type, data)).filter(|(_, data)| !data.is_empty())
+        }).collect();
+        pictures.sort_by_key(|(picture_type, _)| *picture_type != 3);
+        pictures.first().map(|(_, data)| data.to_vec())
+    }
+    // USLT: encoding, language, description, lyrics
+    pub fn lyrics(&self) -> Option<String>{
+        self.frames("USLT").find_map(|frame| {
+            let (encoding, bytes) = frame.data.split_first()?;
+            let (_, text) = split_terminated(*encoding, bytes.get(3..)?);
+            Some(decode_text(*encoding, text).trim_end_matches('\0').to_string()).filter(|text| !text.trim().is_empty())
+        })
+    }
+    // SYLT: encoding, language, time format, content type, description, then text and time pairs.
+    // times in mpeg frames (format 1) are not supported, only milliseconds (format 2)
+    pub fn synced_lyrics(&self) -> Option<Vec<(u32, String)>>{
+        self.frames("SYLT").find_map(|frame| {
+            let (encoding, bytes) = frame.data.split_first()?;
+            if *bytes.get(3)? != 2 { return None }
+            let (_, mut rest) = split_terminated(*encoding, bytes.get(5..)?);
+            let mut lines = Vec::new();
+            while !rest.is_empty(){
+                let (text, remaining) = split_terminated(*encoding, rest);
+                let time = remaining.get(..4)?;
+                lines.push((big_endian(time) as u32, text));
+                rest = &remaining[4..];
+            }
+            Some(lines).filter(|lines| !lines.is_empty())
+        })
+    }
+    // CHAP: element id, start and end time in milliseconds, byte offsets, then frames like TIT2.
+    // the top level CTOC lists the chapters in order, without one they are sorted by time
+    pub fn chapters(&self) -> Vec<Chapter>{
+        let mut chapters:Vec<(String, Chapter)> = self.frames("CHAP").filter_map(|frame| {
+            let (id, rest) = split_terminated(0, &frame.data);
+            let times = rest.get(..16)?;
+            let (frames, unreadable) = Tag::parse_frames(self.version, 0, &rest[16..]);
+            let frames = Tag{version:self.version,frames,unreadable};
+            let chapter = Chapter{
+                start:Duration::from_millis(big_endian(&times[..4]) as u64),
+                end:Duration::from_millis(big_endian(&times[4..8]) as u64),
+                title:frames.text("TIT2").unwrap_or_default(),
+            };
+            Some((id, chapter))
+        }).collect();
+        // CTOC: element id, flags (0x02 top level), entry count, child ids
+        let order:Option<Vec<String>> = self.frames("CTOC").find_map(|frame| {
+            let (_, rest) = split_terminated(0, &frame.data);
+            let (flags, rest) = rest.split_first()?;
+            if flags & 0x02 == 0 { return None }
+            let (count, mut rest) = rest.split_first()?;
+            let mut ids = Vec::new();
+            for _ in 0..*count{
+                let (id, remaining) = split_terminated(0, rest);
+                ids.push(id);
+                rest = remaining;
+            }
+            Some(ids)
+        });
+        match order{
+            Some(order) => chapters.sort_by_key(|(id, chapter)| (order.iter().position(|known| known == id).unwrap_or(usize::MAX), chapter.start)),
+            None => chapters.sort_by_key(|(_, chapter)| chapter.start),
+        }
+        chapters.into_iter().map(|(_, chapter)| chapter).collect()
+    }
+    // POPM: email, rating 1-255 (0 is unknown), play counter; ours is preferred over those of other players
+    pub fn rating(&self) -> Option<u8>{
+        let ratings:Vec<(String, u8)> = self.frames("POPM").filter_map(|frame| {
+            let (email, rest) = split_terminated(0, &frame.data);
+            Some((email, *rest.first()?))
+        }).filter(|(_, rating)| *rating != 0).collect();
+        let (_, rating) = ratings.iter().find(|(email, _)| email == POPM_EMAIL).or_else(|| ratings.first())?;
+        // the ranges most players read, 1, 64, 128, 196 and 255 are what they write
+        Some(match rating{
+            1..=31 => 1,
+            32..=95 => 2,
+            96..=159 => 3,
+            160..=223 => 4,
+            _ => 5,
+        })
+    }
+    // COMM: encoding, language, short description, text; comments without a description are preferred
+    pub fn comment(&self) -> Option<String>{
+        let mut comments:Vec<(String, String)> = self.frames("COMM").filter_map(|frame| {
+            let (encoding, bytes) = frame.data.split_first()?;
+            let (description, text) = split_terminated(*encoding, bytes.get(3..)?);
+            Some((description, decode_text(*encoding, text).trim_end_matches('\0').to_string()))
+        }).collect();
+        comments.sort_by_key(|(description, _)| !description.is_empty());
+        comments.into_iter().map(|(_, text)| text).find(|text| !text.is_empty())
+    }
+}
+
+// who set a POPM rating, every player keeps its own
+const POPM_EMAIL:&str = "rplayer";
+
+// frames that id3v2.4 replaced, with their v2.4 names, None means dropped
+const V23_REPLACED_FRAMES:[(&str, Option<&str>); 5] = [
+    ("TYER", Some("TDRC")), ("TORY", Some("TDOR")), ("TDAT", None), ("TIME", None), ("TRDA", None),
+];
+
+impl Tag{
+    pub fn new() -> Tag{
+        Tag{version:4,frames:Vec::new(),unreadable:Vec::new()}
+    }
+    // frames are kept as they are, only the few that changed between the versions are converted
+    fn into_v24(self) -> Tag{
+        let version = self.version;
+        let frames = self.frames.into_iter().filter_map(|frame| {
+            match version{
+                // v2.2 ids that have no v2.4 name cannot be written
+                2 if frame.id.len() != 4 => None,
+                // PIC has a three letter image format where APIC has a mime type
+                2 if frame.id == "APIC" && frame.data.len()>=4 => {
+                    let mime = match &frame.data[1..4]{
+                        b"PNG" => "image/png",
+                        _ => "image/jpeg",
+                    };
+                    let mut data = vec![frame.data[0]];
+                    data.extend_from_slice(mime.as_bytes());
+                    data.push(0);
+                    data.extend_from_slice(&frame.data[4..]);
+                    Some(Frame{id:frame.id,data})
+                }
+                2 | 3 => match V23_REPLACED_FRAMES.iter().find(|(old, _)| *old == frame.id){
+                    Some((_, Some(new))) => Some(Frame{id:new.to_string(),data:frame.data}),
+                    Some((_, None)) => None,
+                    None => Some(frame),
+                },
+                _ => Some(frame),
+            }
+        }).collect();
+        Tag{version:4,frames,unreadable:self.unreadable}
+    }
+    // replaces the text frame, an empty text removes it
+    pub fn set_text(&mut self, id:&str, text:&str){
+        self.frames.retain(|frame| frame.id != id);
+        if !text.is_empty(){
+            let mut data = vec![3];
+            data.extend_from_slice(text.as_bytes());
+            self.frames.push(Frame{id:id.to_string(),data});
+        }
+    }
+    // replaces our POPM frame, 0 stars removes it; the ratings of other players are left alone
+    pub fn set_rating(&mut self, stars:u8){
+        self.frames.retain(|frame| frame.id != "POPM" || split_terminated(0, &frame.data).0 != POPM_EMAIL);
+        if stars != 0 {
+            let mut data = POPM_EMAIL.as_bytes().to_vec();
+            data.push(0);
+            data.push([1, 64, 128, 196, 255][stars.min(5) as usize - 1]);
+            self.frames.push(Frame{id:String::from("POPM"),data});
+        }
+    }
+    // an id3v2.4 tag without unsynchronisation or padding
+    fn render(&self) -> Vec<u8>{
+        let mut body = Vec::new();
+        for frame in self.frames.iter(){
+            body.extend_from_slice(frame.id.as_bytes());
+            body.extend_from_slice(&to_syncsafe(frame.data.len()));
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&frame.data);
+        }
+        let mut bytes = b"ID3\x04\x00\x00".to_vec();
+        bytes.extend_from_slice(&to_syncsafe(body.len()));
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+}
+impl Default for Tag{
+    fn default() -> Tag{
+        Tag::new()
+    }
+}
+
+// latin-1 with zero padding, what does not fit is cut off
+fn v1_field(text:&str, length:usize) -> Vec<u8>{
+    let mut bytes:Vec<u8> = text.chars().map(|c| if (c as u32)<256 { c as u8 } else { b'?' }).take(length).collect();
+    bytes.resize(length, 0);
+    bytes
+}
+// keeps an id3v1 tag at the end of the file in line with the new text, a v1 tag would otherwise
+// still show values that were removed from the v2 tag
+fn update_v1(audio:&mut [u8], edits:&[(&str, String)]){
+    let start = match audio.len().checked_sub(128){
+        Some(start) if &audio[start..start+3] == b"TAG" => start,
+        _ => return,
+    };
+    for (id, text) in edits{
+        let (offset, length) = match *id{
+            "TIT2" => (3, 30),
+            "TPE1" => (33, 30),
+            "TALB" => (63, 30),
+            "TDRC" => (93, 4),
+            // v1 genres are numbers, 255 leaves the genre to the v2 tag
+            "TCON" => { audio[start+127] = 255; continue }
+            _ => continue,
+        };
+        audio[start+offset..start+offset+length].copy_from_slice(&v1_field(text, length));
+    }
+}
+
+// sets text frames and keeps the id3v1 tag in line
+pub fn write_text_frames(path:&Path, edits:&[(&str, String)]) -> Result<(), anyhow::Error>{
+    rewrite(path, |tag, audio| {
+        for (id, text) in edits{
+            tag.set_text(id, text);
+        }
+        update_v1(audio, edits);
+    })
+}
+pub fn write_rating(path:&Path, stars:u8) -> Result<(), anyhow::Error>{
+    rewrite(path, |tag, _| tag.set_rating(stars))
+}
+
+// edits the tag and the audio after it and rewrites the file as id3v2.4, the new file is written
+// next to the old one and renamed over it so an interrupted write never leaves a broken file
+fn rewrite(path:&Path, edit:impl FnOnce(&mut Tag, &mut Vec<u8>)) -> Result<(), anyhow::Error>{
+    let bytes = fs::read(path)?;
+    let (tag, audio_start) = Tag::from_bytes(&bytes);
+    let mut tag = tag.unwrap_or_default().into_v24();
+    if !tag.unreadable.is_empty() {
+        return Err(anyhow!("the tag has compressed or encrypted frames ({}) that would be lost", tag.unreadable.join(", ")))
+    }
+    let mut audio = bytes[audio_start..].to_vec();
+    edit(&mut tag, &mut audio);
+
+    let name = path.file_name().ok_or_else(|| anyhow!("{} is not a file", path.display()))?;
+    let tmp_path = path.with_file_name(format!(".{}.rplayer-tmp", name.to_string_lossy()));
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(&tag.render())?;
+        file.write_all(&audio)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp_path, fs::metadata(path)?.permissions())
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, path)){
+        fs::remove_file(&tmp_path).ok();
+        return Err(e.into())
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn v22_frame(id:&str, data:&[u8]) -> Vec<u8>{
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+        frame.extend_from_slice(data);
+        frame
+    }
+    fn v23_frame(id:&str, flags:u8, data:&[u8]) -> Vec<u8>{
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, flags]);
+        frame.extend_from_slice(data);
+        frame
+    }
+    fn v24_frame(id:&str, flags:u8, data:&[u8]) -> Vec<u8>{
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&to_syncsafe(data.len()));
+        frame.extend_from_slice(&[0, flags]);
+        frame.extend_from_slice(data);
+        frame
+    }
+    fn tag_bytes(version:u8, flags:u8, body:&[u8]) -> Vec<u8>{
+        let mut bytes = vec![b'I', b'D', b'3', version, 0, flags];
+        bytes.extend_from_slice(&to_syncsafe(body.len()));
+        bytes.extend_from_slice(body);
+        bytes
+    }
+    fn unsynchronise(bytes:&[u8]) -> Vec<u8>{
+        let mut result = Vec::new();
+        for byte in bytes{
+            result.push(*byte);
+            if *byte == 0xff { result.push(0) }
+        }
+        result
+    }
+    fn latin1(text:&str) -> Vec<u8>{
+        let mut data = vec![0];
+        data.extend(text.chars().map(|c| c as u8));
+        data
+    }
+
+    #[test]
+    fn resync_drops_the_zero_after_0xff(){
+        assert_eq!(resync(&[0xff, 0, 0xe0, 0xff, 0, 0]), vec![0xff, 0xe0, 0xff, 0]);
+        assert_eq!(resync(&[0, 0xfe, 0]), vec![0, 0xfe, 0]);
+    }
+
+    #[test]
+    fn v22_frames_get_their_v24_names(){
+        let mut body = v22_frame("TT2", &latin1("Title"));
+        body.extend(v22_frame("TP1", &latin1("Artist")));
+        let (tag, audio_start) = Tag::from_bytes(&tag_bytes(2, 0, &body));
+        let tag = tag.unwrap();
+        assert_eq!(audio_start, 10+body.len());
+        assert_eq!(tag.version, 2);
+        assert_eq!(tag.text("TIT2").as_deref(), Some("Title"));
+        assert_eq!(tag.text("TPE1").as_deref(), Some("Artist"));
+    }
+
+    #[test]
+    fn v23_utf16_text(){
+        let mut data = vec![1, 0xff, 0xfe];
+        data.extend("Tïtle ✓".encode_utf16().flat_map(u16::to_le_bytes));
+        data.extend_from_slice(&[0, 0]);
+        let (tag, _) = Tag::from_bytes(&tag_bytes(3, 0, &v23_frame("TIT2", 0, &data)));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("Tïtle ✓"));
+    }
+
+    #[test]
+    fn v24_lists_several_values(){
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &v24_frame("TCON", 0, b"\x03Rock\0Pop")));
+        assert_eq!(tag.unwrap().text("TCON").as_deref(), Some("Rock; Pop"));
+    }
+
+    #[test]
+    fn padding_ends_the_frames(){
+        let mut body = v24_frame("TIT2", 0, &latin1("Title"));
+        body.extend_from_slice(&[0; 32]);
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &body));
+        assert_eq!(tag.unwrap().frames.len(), 1);
+    }
+
+    #[test]
+    fn v23_unsynchronised_tag(){
+        let body = v23_frame("TIT2", 0, &latin1("ÿa"));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(3, 0x80, &unsynchronise(&body)));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("ÿa"));
+    }
+
+    #[test]
+    fn v24_unsynchronised_frame(){
+        let body = v24_frame("TIT2", 0x02, &unsynchronise(&latin1("ÿa")));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &body));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("ÿa"));
+    }
+
+    #[test]
+    fn extended_headers_are_skipped(){
+        // v2.3: the size leaves out its own four bytes
+        let mut body = vec![0, 0, 0, 6, 0, 0, 0, 0, 0, 0];
+        body.extend(v23_frame("TIT2", 0, &latin1("Three")));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(3, 0x40, &body));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("Three"));
+        // v2.4: a syncsafe size of the whole extended header
+        let mut body = vec![0, 0, 0, 6, 1, 0];
+        body.extend(v24_frame("TIT2", 0, &latin1("Four")));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0x40, &body));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("Four"));
+    }
+
+    #[test]
+    fn v24_frame_flags(){
+        // a group id byte and a data length indicator come before the data
+        let mut data = vec![7, 0, 0, 0, 6];
+        data.extend(latin1("Title"));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &v24_frame("TIT2", 0x41, &data)));
+        assert_eq!(tag.unwrap().text("TIT2").as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn truncated_tags(){
+        // the last frame claims more than there is, the tag claims more than the file has
+        let mut body = v23_frame("TIT2", 0, &latin1("Title"));
+        body.extend(v23_frame("TPE1", 0, &latin1("Artist")));
+        body.truncate(body.len()-3);
+        let mut bytes = tag_bytes(3, 0, &body);
+        bytes[9] += 20;
+        let (tag, audio_start) = Tag::from_bytes(&bytes);
+        let tag = tag.unwrap();
+        assert_eq!(audio_start, bytes.len());
+        assert_eq!(tag.text("TIT2").as_deref(), Some("Title"));
+        assert_eq!(tag.text("TPE1").as_deref(), Some("Art"));
+        // half a frame header
+        let (tag, _) = Tag::from_bytes(&tag_bytes(3, 0, b"TIT2\0\0"));
+        assert!(tag.unwrap().frames.is_empty());
+        assert!(Tag::from_bytes(b"ID3\x03").0.is_none());
+        assert!(Tag::from_bytes(&tag_bytes(5, 0, &[])).0.is_none());
+    }
+
+    #[test]
+    fn lyrics_frames(){
+        let mut body = v24_frame("USLT", 0, b"\x00eng\0First line\nSecond line");
+        let mut sylt = b"\x00eng\x02\x01\0".to_vec();
+        for (text, time) in [("One", 1000u32), ("Two", 2500)]{
+            sylt.extend_from_slice(text.as_bytes());
+            sylt.push(0);
+            sylt.extend_from_slice(&time.to_be_bytes());
+        }
+        body.extend(v24_frame("SYLT", 0, &sylt));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &body));
+        let tag = tag.unwrap();
+        assert_eq!(tag.lyrics().as_deref(), Some("First line\nSecond line"));
+        assert_eq!(tag.synced_lyrics(), Some(vec![(1000, String::from("One")), (2500, String::from("Two"))]));
+    }
+
+    #[test]
+    fn unsupported_sylt_time_format(){
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &v24_frame("SYLT", 0, b"\x00eng\x01\x01\0One\0\0\0\0\x10")));
+        assert_eq!(tag.unwrap().synced_lyrics(), None);
+    }
+
+    fn chapter(id:&str, start:u32, end:u32, title:&str) -> Vec<u8>{
+        let mut data = id.as_bytes().to_vec();
+        data.push(0);
+        for time in [start, end, u32::MAX, u32::MAX]{
+            data.extend_from_slice(&time.to_be_bytes());
+        }
+        data.extend(v24_frame("TIT2", 0, &latin1(title)));
+        v24_frame("CHAP", 0, &data)
+    }
+
+    #[test]
+    fn chapters_follow_the_table_of_contents(){
+        let mut body = chapter("ch1", 0, 60000, "Intro");
+        body.extend(chapter("ch2", 60000, 120000, "Outro"));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &body));
+        let titles:Vec<String> = tag.unwrap().chapters().into_iter().map(|chapter| chapter.title).collect();
+        assert_eq!(titles, ["Intro", "Outro"]);
+        // a top level CTOC decides the order
+        body.extend(v24_frame("CTOC", 0, b"toc\0\x03\x02ch2\0ch1\0"));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(4, 0, &body));
+        let chapters = tag.unwrap().chapters();
+        assert_eq!(chapters[0].title, "Outro");
+        assert_eq!(chapters[0].start, Duration::from_secs(60));
+        assert_eq!(chapters[0].end, Duration::from_secs(120));
+        assert_eq!(chapters[1].title, "Intro");
+    }
+
+    #[test]
+    fn render_reads_back(){
+        let mut tag = Tag::new();
+        tag.set_text("TIT2", "Tïtle ✓");
+        tag.set_text("TPE1", "Artist");
+        tag.frames.push(Frame{id:String::from("APIC"),data:vec![0xff; 300]});
+        let mut bytes = tag.render();
+        let tag_length = bytes.len();
+        bytes.extend_from_slice(b"audio");
+        let (read, audio_start) = Tag::from_bytes(&bytes);
+        let read = read.unwrap();
+        assert_eq!(audio_start, tag_length);
+        assert_eq!(read.version, 4);
+        assert_eq!(read.text("TIT2").as_deref(), Some("Tïtle ✓"));
+        assert_eq!(read.text("TPE1").as_deref(), Some("Artist"));
+        assert_eq!(read.frames("APIC").next().unwrap().data, vec![0xff; 300]);
+        // an empty text removes the frame
+        let mut read = read;
+        read.set_text("TPE1", "");
+        assert_eq!(read.text("TPE1"), None);
+    }
+
+    #[test]
+    fn v23_tags_are_converted(){
+        let mut body = v23_frame("TYER", 0, &latin1("1999"));
+        body.extend(v23_frame("TDAT", 0, &latin1("0101")));
+        body.extend(v23_frame("TIT2", 0, &latin1("Title")));
+        let (tag, _) = Tag::from_bytes(&tag_bytes(3, 0, &body));
+        let (tag, _) = Tag::from_bytes(&tag.unwrap().into_v24().render());
+        let tag = tag.unwrap();
+        assert_eq!(tag.text("TDRC").as_deref(), Some("1999"));
+        assert_eq!(tag.text("TYER"), None);
+        assert_eq!(tag.text("TDAT"), None);
+        assert_eq!(tag.text("TIT2").as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn v22_pictures_get_a_mime_type(){
+        let (tag, _) = Tag::from_bytes(&tag_bytes(2, 0, &v22_frame("PIC", b"\x00PNG\x03\0picture")));
+        let (tag, _) = Tag::from_bytes(&tag.unwrap().into_v24().render());
+        let tag = tag.unwrap();
+        assert_eq!(tag.frames("APIC").next().unwrap().data, b"\x00image/png\0\x03\0picture");
+        assert_eq!(tag.picture().as_deref(), Some(&b"picture"[..]));
+    }
+
+    #[test]
+    fn update_v1_follows_the_edits(){
+        let mut audio = b"audio".to_vec();
+        let mut v1 = b"TAG".to_vec();
+        v1.extend(v1_field("Old title", 30));
+        v1.extend(v1_field("Old artist", 30));
+        v1.extend(v1_field("Old album", 30));
+        v1.extend(v1_field("1990", 4));
+        v1.extend(v1_field("", 30));
+        v1.push(17);
+        audio.extend(&v1);
+        update_v1(&mut audio, &[("TIT2", String::from("A title that is longer than thirty")), ("TPE1", String::new()), ("TCON", String::from("Rock"))]);
+        let v1 = &audio[5..];
+        assert_eq!(&v1[3..33], b"A title that is longer than th");
+        assert_eq!(&v1[33..63], &[0; 30]);
+        assert_eq!(&v1[63..72], b"Old album");
+        assert_eq!(v1[127], 255);
+        // without a v1 tag nothing changes
+        let mut audio = b"audio".to_vec();
+        update_v1(&mut audio, &[("TIT2", String::from("Title"))]);
+        assert_eq!(audio, b"audio");
+    }
+
+    #[test]
+    fn rewrite_keeps_the_audio(){
+        let path = std::env::temp_dir().join(format!("rplayer-id3-test-{}.mp3", std::process::id()));
+        let mut bytes = tag_bytes(3, 0, &v23_frame("TIT2", 0, &latin1("Old")));
+        bytes.extend_from_slice(&[0xff, 0xfb, 0x90, 0x00]);
+        fs::write(&path, &bytes).unwrap();
+        write_text_frames(&path, &[("TIT2", String::from("New"))]).unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (tag, audio_start) = Tag::from_bytes(&written);
+        let tag = tag.unwrap();
+        assert_eq!(tag.version, 4);
+        assert_eq!(tag.text("TIT2").as_deref(), Some("New"));
+        assert_eq!(&written[audio_start..], &[0xff, 0xfb, 0x90, 0x00]);
+    }
+
+    #[test]
+    fn popm_ratings(){
+        let popm = |email:&str, rating:u8| {
+            let mut data = email.as_bytes().to_vec();
+            data.extend_from_slice(&[0, rating]);
+            v23_frame("POPM", 0, &data)
+        };
+        let tag = |body:Vec<u8>| Tag::from_bytes(&tag_bytes(3, 0, &body)).0.unwrap();
+        assert_eq!(tag(popm("other", 1)).rating(), Some(1));
+        assert_eq!(tag(popm("other", 200)).rating(), Some(4));
+        assert_eq!(tag(popm("other", 0)).rating(), None);
+        // ours is preferred over the rating of another player
+        let mut body = popm("other", 255);
+        body.extend(popm(POPM_EMAIL, 64));
+        assert_eq!(tag(body).rating(), Some(2));
+    }
+
+    #[test]
+    fn write_rating_reads_back(){
+        let path = std::env::temp_dir().join(format!("rplayer-id3-test-rating-{}.mp3", std::process::id()));
+        let mut data = b"other".to_vec();
+        data.extend_from_slice(&[0, 255]);
+        let mut bytes = tag_bytes(3, 0, &v23_frame("POPM", 0, &data));
+        bytes.extend_from_slice(b"audio");
+        fs::write(&path, &bytes).unwrap();
+        write_rating(&path, 3).unwrap();
+        let rated = Tag::from_bytes(&fs::read(&path).unwrap()).0.unwrap();
+        write_rating(&path, 0).unwrap();
+        let unrated = Tag::from_bytes(&fs::read(&path).unwrap()).0.unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(rated.rating(), Some(3));
+        assert_eq!(rated.frames("POPM").count(), 2);
+        // removing ours leaves the rating of the other player
+        assert_eq!(unrated.rating(), Some(5));
+        assert_eq!(unrated.frames("POPM").count(), 1);
+    }
+
+    #[test]
+    fn unreadable_frames_are_not_rewritten(){
+        let path = std::env::temp_dir().join(format!("rplayer-id3-test-compressed-{}.mp3", std::process::id()));
+        let mut body = v23_frame("TIT2", 0, &latin1("Old"));
+        body.extend(v23_frame("TXXX", 0x80, &[0, 0, 0, 8, 0x78, 0x9c]));
+        let mut bytes = tag_bytes(3, 0, &body);
+        bytes.extend_from_slice(b"audio");
+        fs::write(&path, &bytes).unwrap();
+        let result = write_text_frames(&path, &[("TIT2", String::from("New"))]);
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(result.unwrap_err().to_string().contains("TXXX"));
+        assert_eq!(written, bytes);
+    }
+}
@@ -5,21 +5,66 @@ use std::time::{Instant,Duration};
 use std::thread::sleep;
 use std::thread::spawn;
 use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicBool};
 use futures::{future::FutureExt, select, StreamExt};
 
 
 use crossterm::{Result, execute, terminal, style::Colorize, terminal::{EnterAlternateScreen, LeaveAlternateScreen, enable_raw_mode, disable_raw_mode, Clear, ClearType}, event::{Event, EventStream, KeyCode, KeyEvent, KeyModifiers}, cursor::{Hide, Show}, QueueableCommand};
-use crate::audio_controller::AudioPlayer;
-use std::path::{PathBuf};
+use crate::audio_controller::{AudioPlayer, Levels};
+use std::path::{Path, PathBuf};
 use crossterm::style::style;
 use crate::graphics::{Square, draw_text, Drawable};
-use crate::file_explorer::FileExplorer;
+use crate::file_explorer::{Column, FileExplorer, RatingFilter};
+use crate::library::Rating;
+use crate::queue_view::QueueView;
+use crate::search::Search;
+use crate::tag_editor::TagEditor;
+use crate::organise_view::OrganiseView;
+use crate::album_art::{AlbumArt, Protocol};
+use crate::lyrics_view::LyricsView;
+use crate::chapter_view::ChapterView;
+use crate::resume::ResumeState;
+use crate::bookmarks::{Bookmark, Bookmarks};
+use crate::bookmark_view::BookmarkView;
+use crate::history::{History, Play, Recorder};
+use crate::history_view::HistoryView;
+use crate::library_view::{Grouping, LibraryView};
+use crate::directory_browser::DirectoryBrowser;
+use crate::file_manager::{FileManager, RepeatMode, ScanOptions, SortKey};
+use crate::playlist::Playlist;
+use crate::watcher::{LibraryWatcher, LibraryChange};
 
 
 pub mod audio_controller;
 pub mod file_manager;
 pub mod graphics;
 pub mod file_explorer;
+pub mod library;
+pub mod watcher;
+pub mod playlist;
+pub mod cue;
+pub mod queue_view;
+pub mod search;
+pub mod library_view;
+pub mod directory_browser;
+pub mod id3;
+pub mod tag_editor;
+pub mod organise;
+pub mod organise_view;
+pub mod album_art;
+pub mod lyrics;
+pub mod lyrics_view;
+pub mod chapter_view;
+pub mod resume;
+pub mod bookmarks;
+pub mod bookmark_view;
+pub mod history;
+pub mod history_view;
+
+// how often the resume state is written while the player runs
+const RESUME_SAVE_INTERVAL:Duration = Duration::from_secs(30);
+
 struct FramerateClock{
     framerate: u64,
     before: Instant,
@@ -51,6 +96,244 @@ pub enum AppAction{
     Start,
     Pause,
     Shuffle,
+    CycleRepeat,
+    CycleSort,
+    ReverseSort,
+    SavePlaylist,
+    AddToQueue,
+    PlayNext,
+    ToggleQueueView,
+    CycleView,
+    ToggleNode,
+    ToggleDirectoryView,
+    ToggleLyricsView,
+    ToggleChapterView,
+    NextChapter,
+    PrevChapter,
+    AddBookmark,
+    ToggleBookmarkView,
+    ToggleHistoryView,
+    ParentDirectory,
+    LoadDirectory,
+    ToggleFavourite,
+    MoveSelection(i32),
+    MovePage(i32),
+    SelectFirst,
+    SelectLast,
+    PlaySelected,
+    StartSearch,
+    EditTags,
+    ToggleMark,
+    ClearMarks,
+    Organise,
+    UndoOrganise,
+    TextInput(char),
+    TextBackspace,
+    TextConfirm,
+    TextCancel,
+    TextNextField,
+    TextPrevField,
+    NextMatch,
+    PrevMatch,
+    MoveQueued(i32),
+    RemoveQueued,
+    SetRating(u8),
+    ToggleFavouriteTrack,
+    CycleRatingFilter,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum View{
+    Files,
+    Queue,
+    Library,
+    Directories,
+    Lyrics,
+    Chapters,
+    Bookmarks,
+    History,
+}
+// the file name typed for W, an existing file is only replaced once Enter is pressed again
+struct PlaylistPrompt{
+    dir:PathBuf,
+    name:String,
+    overwrite:bool,
+}
+impl PlaylistPrompt{
+    // a name without a directory is saved next to the default location
+    fn location(&self) -> PathBuf{
+        self.dir.join(self.name.trim())
+    }
+}
+// the widgets sharing the list area, only the one of the current view is drawn
+struct Browser{
+    view:View,
+    file_explorer:FileExplorer,
+    queue_view:QueueView,
+    library_view:LibraryView,
+    directory_browser:DirectoryBrowser,
+    lyrics_view:LyricsView,
+    chapter_view:ChapterView,
+    bookmark_view:BookmarkView,
+    history_view:HistoryView,
+    // hides tracks below a rating, in the file view only
+    rating_filter:RatingFilter,
+    // the bookmark whose name is being typed
+    new_bookmark:Option<Bookmark>,
+    new_playlist:Option<PlaylistPrompt>,
+    search:Option<Search>,
+    tag_editor:Option<TagEditor>,
+    organise_view:Option<OrganiseView>,
+    // the last pattern used, the prompt starts with it
+    organise_pattern:String,
+    // shared with the key thread, which sends keys as text while it is set
+    is_typing:Arc<AtomicBool>,
+}
+impl Browser{
+    // the key thread takes keys as text as soon as it sends an action that opens a prompt,
+    // a prompt that was not opened has to end that again
+    fn stop_typing(&self){
+        self.is_typing.store(false, atomic::Ordering::SeqCst);
+    }
+    // the tracks that play, queue and edit actions apply to, the queue view has none
+    fn selected_tracks(&self, file_manager:&FileManager) -> Vec<PathBuf>{
+        match self.view{
+            View::Files => {
+                let marked = self.file_explorer.marked(file_manager);
+                if !marked.is_empty() { return marked }
+                self.file_explorer.highlighted().map(|position| file_manager.path_at(position)).into_iter().collect()
+            }
+            View::Library => self.library_view.selected_tracks(),
+            View::Queue | View::Directories | View::Lyrics | View::Chapters | View::Bookmarks | View::History => Vec::new(),
+        }
+    }
+    // the explorer only lists the matches while the query is typed
+    fn update_search(&mut self, file_manager:&mut FileManager){
+        let search = match self.search.as_ref(){
+            Some(search) => search,
+            None => return,
+        };
+        self.file_explorer.set_query(&search.query);
+        let origin = search.origin.filter(|_| search.query.is_empty());
+        self.refilter(file_manager);
+        if let Some(origin) = origin{
+            self.file_explorer.select_position(origin);
+        }
+    }
+    // the explorer lists the matches while a query is typed, and only the tracks the rating filter lets through
+    fn refilter(&mut self, file_manager:&mut FileManager){
+        let rating_filter = self.rating_filter;
+        let mut positions = match self.search.as_ref().filter(|search| search.is_typing && !search.query.is_empty()){
+            Some(search) => Some(search.matches(file_manager)),
+            None if rating_filter == RatingFilter::All => None,
+            None => Some((0..file_manager.file_paths.len()).collect()),
+        };
+        if let Some(positions) = positions.as_mut().filter(|_| rating_filter != RatingFilter::All) {
+            positions.retain(|position| {
+                let path = file_manager.path_at(*position);
+                rating_filter.matches(file_manager.rating(&path))
+            });
+        }
+        self.file_explorer.set_filter(positions);
+    }
+    fn draw_search(&self, stdout:&mut Stdout, x:u16, y:u16) -> Result<()>{
+        if let Some(bookmark) = self.new_bookmark.as_ref(){
+            let prompt = format!("Bookmark at {}: {}_", graphics::duration_to_mmss(bookmark.time), bookmark.name);
+            draw_text(stdout, style(prompt).yellow(), x, y)?;
+        }
+        if let Some(playlist) = self.new_playlist.as_ref(){
+            let prompt = match playlist.overwrite{
+                true => format!("{} exists, Enter replaces it", playlist.location().display()),
+                false => format!("Save playlist in {}: {}_", playlist.dir.display(), playlist.name),
+            };
+            draw_text(stdout, style(prompt).yellow(), x, y)?;
+        }
+        if let Some(search) = self.search.as_ref().filter(|search| search.is_typing){
+            draw_text(stdout, style(format!("/{}", search.query)).yellow(), x, y)?;
+        }
+        Ok(())
+    }
+    // the marked tracks or the whole list, in the library views the highlighted node
+    fn organise_tracks(&self, file_manager:&FileManager) -> Vec<PathBuf>{
+        match self.view{
+            View::Files if self.file_explorer.marked(file_manager).is_empty() => file_manager.file_paths.clone(),
+            _ => self.selected_tracks(file_manager),
+        }
+    }
+    // files moved by organise or its undo keep their place in the list
+    fn apply_moves(&mut self, outcome:&organise::Outcome, file_manager:&mut FileManager, status_line:&mut graphics::StatusLine){
+        for (from, to) in outcome.moved.iter(){
+            file_manager.apply_change(&LibraryChange::Renamed(from.clone(), to.clone()));
+            self.file_explorer.invalidate(from);
+        }
+        self.file_explorer.clear_marks();
+        self.file_explorer.set_files(file_manager.file_paths.len());
+        self.refilter(file_manager);
+        self.file_explorer.follow(file_manager.position());
+        if self.view == View::Library{
+            self.library_view.rebuild(file_manager);
+        }
+        match outcome.errors.as_slice(){
+            [] => status_line.set_message(format!("Moved {} tracks", outcome.moved.len())),
+            [error, ..] => status_line.set_message(format!("Moved {} tracks, {} failed, e.g. {}", outcome.moved.len(), outcome.errors.len(), error)),
+        }
+    }
+    // writes the edited tags to every track of the editor and reads them back
+    fn save_tags(&mut self, file_manager:&mut FileManager, status_line:&mut graphics::StatusLine){
+        let tag_editor = match self.tag_editor.take(){
+            Some(tag_editor) => tag_editor,
+            None => return,
+        };
+        let edits = match tag_editor.edits(){
+            Ok(edits) => edits,
+            Err(e) => {
+                status_line.set_message(format!("Nothing saved, {}", e));
+                return
+            }
+        };
+        let paths = tag_editor.paths();
+        if edits.is_empty() || paths.is_empty() { return }
+        let mut failed = Vec::new();
+        for path in paths.iter(){
+            if let Err(e) = id3::write_text_frames(path, &edits){
+                failed.push(format!("{}: {}", path.display(), e));
+            }
+            file_manager.refresh_metadata(path);
+            self.file_explorer.invalidate(path);
+        }
+        self.file_explorer.clear_marks();
+        if self.view == View::Library{
+            self.library_view.rebuild(file_manager);
+        }
+        match failed.as_slice(){
+            [] => status_line.set_message(format!("Saved the tags of {} tracks", paths.len())),
+            [error] => status_line.set_message(format!("Cannot save the tags of {}", error)),
+            [error, ..] => status_line.set_message(format!("Cannot save the tags of {} tracks, e.g. {}", failed.len(), error)),
+        }
+    }
+    fn draw(&mut self, stdout:&mut Stdout, file_manager:&mut FileManager, x:u16, y:u16, width:u16){
+        if let Some(tag_editor) = self.tag_editor.as_ref(){
+            tag_editor.draw(stdout, x, y, width);
+            return
+        }
+        if let Some(organise_view) = self.organise_view.as_ref(){
+            organise_view.draw(stdout, x, y, width);
+            return
+        }
+        match self.view{
+            View::Files => self.file_explorer.draw(stdout, file_manager, self.history_view.history(), x, y, width),
+            View::Queue => self.queue_view.draw(stdout, file_manager, x, y, width),
+            View::Library => self.library_view.draw(stdout, file_manager, x, y, width),
+            View::Directories => self.directory_browser.draw(stdout, x, y, width),
+            View::Lyrics => self.lyrics_view.draw(stdout, x, y, width),
+            View::Chapters => self.chapter_view.draw(stdout, x, y, width),
+            View::Bookmarks => {
+                let current = file_manager.get_current();
+                self.bookmark_view.draw(stdout, &std::path::absolute(&current).unwrap_or(current), x, y, width)
+            }
+            View::History => self.history_view.draw(stdout, x, y, width),
+        }
+    }
 }
 
 fn relative_size(val:f32, size:u16) -> u16{
@@ -58,18 +341,238 @@ fn relative_size(val:f32, size:u16) -> u16{
 }
 pub struct App{
     stdout: Stdout,
-    path: PathBuf,
+    paths: Vec<PathBuf>,
+    scan_options: ScanOptions,
+    columns: Vec<Column>,
+    art_protocol: Protocol,
+    // where the last session stopped, used when the same paths are played again
+    resume_state: Option<ResumeState>,
+    bookmarks_location: PathBuf,
+    // ratings also go into the POPM frame of the files, not only into the library index
+    write_ratings: bool,
     size:(u16,u16),
 }
 impl App {
-    pub fn new(path:PathBuf) -> App {
+    // paths are either directories to scan or a single playlist file
+    pub fn new(paths:Vec<PathBuf>, scan_options:ScanOptions) -> App {
         App {
             stdout: stdout(),
-            path,
+            paths,
+            scan_options,
+            columns: Column::DEFAULT.to_vec(),
+            art_protocol: Protocol::detect(),
+            resume_state: None,
+            bookmarks_location: bookmarks::default_location(),
+            write_ratings: false,
             size: terminal::size().unwrap()
         }
     }
-    pub async fn process_key_events(sender:mpsc::Sender<AppAction>){
+    // the explorer columns, from left to right
+    pub fn set_columns(&mut self, columns:Vec<Column>){
+        self.columns = columns;
+    }
+    // how the album art is drawn, Protocol::Off hides it
+    pub fn set_art_protocol(&mut self, protocol:Protocol){
+        self.art_protocol = protocol;
+    }
+    // the JSON file the bookmarks are read from and written to
+    pub fn set_bookmarks_location(&mut self, location:PathBuf){
+        self.bookmarks_location = location;
+    }
+    pub fn set_write_ratings(&mut self, write_ratings:bool){
+        self.write_ratings = write_ratings;
+    }
+    pub fn set_resume_state(&mut self, state:ResumeState){
+        self.resume_state = Some(state);
+    }
+    // the track, order, timestamp and volume of the last session, the player stays paused
+    fn resume(&mut self, audio_player:&mut AudioPlayer, browser:&mut Browser, status_line:&mut graphics::StatusLine){
+        let state = match self.resume_state.take().filter(|state| state.is_for(&self.paths)){
+            Some(state) => state,
+            None => return,
+        };
+        if !state.order.is_empty() {
+            audio_player.file_manager.restore_order(&state.order);
+        }
+        audio_player.set_volume(state.volume);
+        let position = audio_player.file_manager.position_of(&state.track);
+        if position.is_some_and(|position| audio_player.set_track_at(position)) {
+            audio_player.set_timestamp(state.timestamp);
+            status_line.set_message(format!("Resumed {} at {}", state.track.display(), graphics::duration_to_mmss(state.timestamp)));
+        }
+        // changing the track starts the stream, playback only starts with Space
+        audio_player.pause();
+        browser.file_explorer.jump_to(audio_player.file_manager.position());
+    }
+    // plays a bookmarked or recently played track from `time`, its folder is loaded first when the track is not in the list
+    // returns whether another folder was loaded
+    fn play_from(&mut self, path:&Path, time:Duration, message:String, audio_player:&mut AudioPlayer, browser:&mut Browser, status_line:&mut graphics::StatusLine) -> bool{
+        // bookmarks and the history have absolute paths, the list has them the way they were given
+        let find = |file_manager:&FileManager| (0..file_manager.file_paths.len())
+            .find(|position| std::path::absolute(file_manager.path_at(*position)).ok().as_deref() == Some(path));
+        let mut position = find(&audio_player.file_manager);
+        let mut is_loaded = false;
+        if position.is_none() {
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            is_loaded = self.load_directory(dir, audio_player, browser, status_line);
+            position = find(&audio_player.file_manager);
+        }
+        match position{
+            Some(position) if audio_player.set_track_at(position) => {
+                audio_player.set_timestamp(time);
+                if !audio_player.is_running() {
+                    audio_player.run().unwrap();
+                }
+                browser.file_explorer.jump_to(position);
+                status_line.set_message(message);
+            }
+            Some(_) => status_line.set_message(format!("Cannot play {}", path.display())),
+            None => status_line.set_message(format!("{} is gone", path.display())),
+        }
+        is_loaded
+    }
+    // the row of the track shows the new play count
+    fn record_play(play:Play, browser:&mut Browser, status_line:&mut graphics::StatusLine){
+        browser.file_explorer.invalidate(&play.path);
+        if let Err(e) = browser.history_view.record(play) {
+            status_line.set_message(format!("Cannot record the play: {}", e));
+        }
+    }
+    // changes the rating of the highlighted or marked tracks, a filter may hide them afterwards
+    fn rate(&self, edit:impl Fn(Rating) -> Rating, audio_player:&mut AudioPlayer, browser:&mut Browser, status_line:&mut graphics::StatusLine){
+        let tracks = browser.selected_tracks(&audio_player.file_manager);
+        if tracks.is_empty() {
+            status_line.set_message(String::from("No tracks to rate here"));
+            return
+        }
+        let file_manager = &mut audio_player.file_manager;
+        let mut failed = Vec::new();
+        for path in tracks.iter(){
+            let rating = edit(file_manager.rating(path));
+            file_manager.set_rating(path, rating);
+            // the tag of a cue track belongs to the whole album
+            if self.write_ratings && file_manager.segment(path).is_none() {
+                if let Err(e) = id3::write_rating(path, rating.stars) {
+                    failed.push(format!("{}: {}", path.display(), e));
+                }
+                file_manager.refresh_metadata(path);
+            }
+            browser.file_explorer.invalidate(path);
+        }
+        if let Err(e) = file_manager.save_library() {
+            status_line.set_message(format!("Cannot save the ratings: {}", e));
+            return
+        }
+        let highlighted = browser.file_explorer.highlighted();
+        browser.refilter(file_manager);
+        if let Some(position) = highlighted {
+            browser.file_explorer.select_nearest(position);
+        }
+        let rating = file_manager.rating(&tracks[0]);
+        let description = match (rating.stars, rating.is_favourite){
+            (0, false) => String::from("unrated"),
+            (0, true) => String::from("a favourite"),
+            (stars, favourite) => format!("{} stars{}", stars, if favourite { ", a favourite" } else { "" }),
+        };
+        match failed.as_slice(){
+            [] if tracks.len() == 1 => status_line.set_message(format!("{} is {}", tracks[0].display(), description)),
+            [] => status_line.set_message(format!("Rated {} tracks", tracks.len())),
+            [error, ..] => status_line.set_message(format!("Rated {} tracks, cannot write the tag of {} of them, e.g. {}", tracks.len(), failed.len(), error)),
+        }
+    }
+    fn save_resume_state(&self, audio_player:&AudioPlayer){
+        // the state is only a convenience, failing to write it must not keep the player from closing
+        let track = audio_player.get_current_path().unwrap_or_else(|| audio_player.file_manager.get_current());
+        ResumeState::new(&self.paths, &audio_player.file_manager, track, audio_player.get_timestamp(), audio_player.get_volume()).save().ok();
+    }
+    fn playlist_path(&self) -> Option<&PathBuf>{
+        self.paths.first().filter(|path| playlist::is_playlist(path))
+    }
+    fn load_file_manager(&self, status_line:&mut graphics::StatusLine) -> anyhow::Result<FileManager>{
+        match self.playlist_path(){
+            Some(path) if self.paths.len() == 1 => {
+                let playlist = Playlist::load(path)?;
+                let file_manager = FileManager::from_playlist(&playlist)?;
+                let missing = playlist.invalid + file_manager.file_paths.iter().filter(|path| file_manager.is_bad(path)).count();
+                if missing != 0 {
+                    status_line.set_message(format!("{} playlist entries are missing", missing));
+                }
+                Ok(file_manager)
+            }
+            Some(_) => Err(anyhow::anyhow!("A playlist has to be the only path")),
+            None => FileManager::from_roots(&self.paths, &self.scan_options),
+        }
+    }
+    // the prompt starts with the loaded playlist, or with playlist.m3u8 in the first scanned directory
+    fn playlist_prompt(&self) -> PlaylistPrompt{
+        let location = match self.playlist_path(){
+            Some(path) => path.clone(),
+            None => self.paths[0].join("playlist.m3u8"),
+        };
+        let dir = location.parent().map(Path::to_path_buf).unwrap_or_default();
+        let name = location.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        PlaylistPrompt{dir,name,overwrite:false}
+    }
+    fn save_playlist(audio_player:&AudioPlayer, location:&Path, status_line:&mut graphics::StatusLine){
+        match audio_player.file_manager.to_playlist().save(location){
+            Ok(()) => status_line.set_message(format!("Saved playlist to {}", location.display())),
+            Err(e) => status_line.set_message(format!("Cannot save playlist to {}: {}", location.display(), e)),
+        }
+    }
+    fn start_dir(&self) -> PathBuf{
+        match self.paths.first(){
+            Some(path) if path.is_file() => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            Some(path) => path.clone(),
+            None => PathBuf::from("."),
+        }
+    }
+    // replaces the list with the mp3 files of another folder, the old list stays if that fails
+    fn load_directory(&mut self, dir:PathBuf, audio_player:&mut AudioPlayer, browser:&mut Browser, status_line:&mut graphics::StatusLine) -> bool{
+        audio_player.file_manager.save_library().ok();
+        let loaded = FileManager::from_roots(std::slice::from_ref(&dir), &self.scan_options)
+            .and_then(|file_manager| audio_player.load(file_manager))
+            // only once a track of the new folder plays, its queue would otherwise come first
+            .map(|previous| audio_player.file_manager.keep_settings(&previous));
+        if let Err(e) = loaded {
+            status_line.set_message(format!("Cannot load {}: {}", dir.display(), e));
+            return false
+        }
+        status_line.set_message(format!("Loaded {}", dir.display()));
+        self.paths = vec![dir];
+        browser.view = View::Files;
+        browser.search = None;
+        browser.file_explorer.clear_marks();
+        browser.file_explorer.set_files(audio_player.file_manager.file_paths.len());
+        browser.refilter(&mut audio_player.file_manager);
+        browser.file_explorer.jump_to(audio_player.file_manager.position());
+        true
+    }
+    // a minimal screen with just the directory browser, None when the player is closed instead
+    fn pick_directory(&mut self, rx:&mpsc::Receiver<AppAction>, square:&Square, directory_browser:&mut DirectoryBrowser, status_line:&mut graphics::StatusLine) -> Result<Option<PathBuf>>{
+        let mut clock = FramerateClock::new(15);
+        loop {
+            self.stdout.queue(Clear(ClearType::All))?;
+            square.draw(&mut self.stdout, 0, 0)?;
+            directory_browser.draw(&mut self.stdout, 1, 1, relative_size(0.8, self.size.0 - 6));
+            let help = style(String::from("Enter opens a folder, Backspace goes up, L loads the folder, B adds a favourite")).dark_grey();
+            draw_text(&mut self.stdout, help, 1, square.height)?;
+            status_line.draw(&mut self.stdout, 1, self.size.1 - 1)?;
+            self.stdout.flush()?;
+            if let Ok(action) = rx.try_recv() {
+                match action {
+                    AppAction::Exit => return Ok(None),
+                    AppAction::LoadDirectory => {
+                        let dir = directory_browser.selected().unwrap_or_else(|| directory_browser.current_dir().to_path_buf());
+                        return Ok(Some(dir))
+                    }
+                    _ => App::match_directory_actions(action, directory_browser, status_line),
+                }
+            }
+            clock.sleep_if_needed();
+        }
+    }
+    // while a search query or a tag is typed the keys are text, not shortcuts
+    pub async fn process_key_events(sender:mpsc::Sender<AppAction>, is_typing:Arc<AtomicBool>){
         let mut reader = EventStream::new();
         loop {
             let mut event = reader.next().fuse();
@@ -77,6 +580,85 @@ impl App {
             maybe_event = event => {
                 match maybe_event {
                     Some(Ok(event)) => {
+                        if is_typing.load(atomic::Ordering::SeqCst) {
+                            if let Event::Key(KeyEvent{code, modifiers}) = event {
+                                match code {
+                                    KeyCode::Char(c) if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                                        sender.send(AppAction::TextInput(c)).unwrap();
+                                        continue;
+                                    }
+                                    KeyCode::Backspace => {
+                                        sender.send(AppAction::TextBackspace).unwrap();
+                                        continue;
+                                    }
+                                    KeyCode::Tab | KeyCode::Down => {
+                                        sender.send(AppAction::TextNextField).unwrap();
+                                        continue;
+                                    }
+                                    KeyCode::BackTab | KeyCode::Up => {
+                                        sender.send(AppAction::TextPrevField).unwrap();
+                                        continue;
+                                    }
+                                    KeyCode::Enter => {
+                                        is_typing.store(false, atomic::Ordering::SeqCst);
+                                        sender.send(AppAction::TextConfirm).unwrap();
+                                        continue;
+                                    }
+                                    KeyCode::Esc => {
+                                        is_typing.store(false, atomic::Ordering::SeqCst);
+                                        sender.send(AppAction::TextCancel).unwrap();
+                                        continue;
+                                    }
+                                    _ => (),
+                                }
+                            }
+                        }
+                        if event == Event::Key(KeyCode::Char('/').into()) {
+                            is_typing.store(true, atomic::Ordering::SeqCst);
+                            sender.send(AppAction::StartSearch).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('t').into()) {
+                            is_typing.store(true, atomic::Ordering::SeqCst);
+                            sender.send(AppAction::EditTags).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('g').into()) {
+                            is_typing.store(true, atomic::Ordering::SeqCst);
+                            sender.send(AppAction::Organise).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('i').into()) {
+                            is_typing.store(true, atomic::Ordering::SeqCst);
+                            sender.send(AppAction::AddBookmark).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('I'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('I').into())
+                        {
+                            sender.send(AppAction::ToggleBookmarkView).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('U'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('U').into())
+                        {
+                            sender.send(AppAction::UndoOrganise).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('m').into()) {
+                            sender.send(AppAction::ToggleMark).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('M'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('M').into())
+                        {
+                            sender.send(AppAction::ClearMarks).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('n').into()) {
+                            sender.send(AppAction::NextMatch).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('N'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('N').into())
+                        {
+                            sender.send(AppAction::PrevMatch).unwrap();
+                        }
 
                        if event == Event::Key(KeyCode::Up.into()) {
                             sender.send(AppAction::IncreaseVolume).unwrap();
@@ -88,6 +670,116 @@ impl App {
                         if event == Event::Key(KeyCode::Char('s').into()) {
                             sender.send(AppAction::Shuffle).unwrap();
                         }
+                        if event == Event::Key(KeyCode::Char('r').into()) {
+                            sender.send(AppAction::CycleRepeat).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('o').into()) {
+                            sender.send(AppAction::CycleSort).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('O'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('O').into())
+                        {
+                            sender.send(AppAction::ReverseSort).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('w').into()) {
+                            is_typing.store(true, atomic::Ordering::SeqCst);
+                            sender.send(AppAction::SavePlaylist).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('e').into()) {
+                            sender.send(AppAction::AddToQueue).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('p').into()) {
+                            sender.send(AppAction::PlayNext).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('q').into()) {
+                            sender.send(AppAction::ToggleQueueView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('v').into()) {
+                            sender.send(AppAction::CycleView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('f').into()) {
+                            sender.send(AppAction::ToggleDirectoryView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('y').into()) {
+                            sender.send(AppAction::ToggleLyricsView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('h').into()) {
+                            sender.send(AppAction::ToggleHistoryView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('c').into()) {
+                            sender.send(AppAction::ToggleChapterView).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char(']').into()) {
+                            sender.send(AppAction::NextChapter).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('[').into()) {
+                            sender.send(AppAction::PrevChapter).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Backspace.into()) {
+                            sender.send(AppAction::ParentDirectory).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('l').into()) {
+                            sender.send(AppAction::LoadDirectory).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('b').into()) {
+                            sender.send(AppAction::ToggleFavourite).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Tab.into()) {
+                            sender.send(AppAction::ToggleNode).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('j').into()) {
+                            sender.send(AppAction::MoveSelection(1)).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('k').into()) {
+                            sender.send(AppAction::MoveSelection(-1)).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::PageDown.into()) {
+                            sender.send(AppAction::MovePage(1)).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::PageUp.into()) {
+                            sender.send(AppAction::MovePage(-1)).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Home.into()) {
+                            sender.send(AppAction::SelectFirst).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::End.into()) {
+                            sender.send(AppAction::SelectLast).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Enter.into()) {
+                            sender.send(AppAction::PlaySelected).unwrap();
+                        }
+                        // uppercase letters arrive with the shift modifier
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('J'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('J').into())
+                        {
+                            sender.send(AppAction::MoveQueued(1)).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('K'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('K').into())
+                        {
+                            sender.send(AppAction::MoveQueued(-1)).unwrap();
+                        }
+                        if let Event::Key(KeyEvent{code:KeyCode::Char(c @ '0'..='5'), modifiers:KeyModifiers::NONE}) = event {
+                            sender.send(AppAction::SetRating(c as u8 - b'0')).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('F'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('F').into())
+                        {
+                            sender.send(AppAction::ToggleFavouriteTrack).unwrap();
+                        }
+                        if event == Event::Key(KeyEvent{
+                                            code:KeyCode::Char('S'),
+                                            modifiers:KeyModifiers::SHIFT}) || event == Event::Key(KeyCode::Char('S').into())
+                        {
+                            sender.send(AppAction::CycleRatingFilter).unwrap();
+                        }
+                        if event == Event::Key(KeyCode::Char('x').into()) || event == Event::Key(KeyCode::Delete.into()) {
+                            sender.send(AppAction::RemoveQueued).unwrap();
+                        }
                         if event == Event::Key(KeyEvent{
                                             code:KeyCode::Right,
                                             modifiers:KeyModifiers::CONTROL}) || event == Event::Key(KeyCode::Char('d').into())
@@ -134,7 +826,25 @@ impl App {
         }
         }
     }
-    fn match_key_actions(action:AppAction,audio_player:&mut AudioPlayer,file_explorer:&mut FileExplorer){
+    // shared by the directory view and the folder picker shown when nothing could be loaded
+    fn match_directory_actions(action:AppAction,directory_browser:&mut DirectoryBrowser,status_line:&mut graphics::StatusLine){
+        match action {
+            AppAction::PlaySelected => directory_browser.open_selected(),
+            AppAction::ParentDirectory => directory_browser.open_parent(),
+            AppAction::ToggleFavourite => match directory_browser.toggle_favourite() {
+                Ok(true) => status_line.set_message(String::from("Added to the favourite folders")),
+                Ok(false) => status_line.set_message(String::from("Removed from the favourite folders")),
+                Err(e) => status_line.set_message(format!("Cannot save the favourite folders: {}", e)),
+            },
+            AppAction::MoveSelection(n) => directory_browser.move_selection_by(n),
+            AppAction::MovePage(n) => directory_browser.move_selection_by(n*directory_browser.page_size() as i32),
+            AppAction::SelectFirst => directory_browser.select(0),
+            AppAction::SelectLast => directory_browser.select(usize::MAX),
+            _ => (),
+        }
+    }
+    fn match_key_actions(action:AppAction,audio_player:&mut AudioPlayer,browser:&mut Browser,status_line:&mut graphics::StatusLine){
+        let file_explorer = &mut browser.file_explorer;
         match action {
             AppAction::Start => {
                 if !audio_player.is_running() {
@@ -152,24 +862,23 @@ impl App {
             AppAction::ChangeTrack(n) =>{
                 if n>=0{
                     for _ in 0..n{
-                        file_explorer.move_down();
                         audio_player.set_next_track_in_dir();
                     }
                 }
                 else{
                     for _ in 0..(-n){
-                        file_explorer.move_up();
                         audio_player.set_prev_track_in_dir();
                     }
                 }
+                file_explorer.follow(audio_player.file_manager.position());
             }
             AppAction::NextTrack => {
-                file_explorer.move_down();
                 audio_player.set_next_track_in_dir();
+                file_explorer.follow(audio_player.file_manager.position());
             },
             AppAction::PrevTrack => {
-                file_explorer.move_up();
                 audio_player.set_prev_track_in_dir();
+                file_explorer.follow(audio_player.file_manager.position());
             },
             AppAction::MoveTimestampBySecs(delta) => {
                 if delta < 0 {
@@ -180,18 +889,383 @@ impl App {
             },
             AppAction::Shuffle => {
                 audio_player.file_manager.toggle_shuffle();
-                if !audio_player.file_manager.is_shuffled() {
-                    let index = audio_player.file_manager.current_index();
-                    file_explorer.set_viewport(index);
-                    file_explorer.set_highlight(index);
+                // the rows moved, so the cursor goes back to the playing track
+                browser.refilter(&mut audio_player.file_manager);
+                browser.file_explorer.jump_to(audio_player.file_manager.position());
+            }
+            AppAction::CycleRepeat => audio_player.file_manager.cycle_repeat(),
+            AppAction::CycleSort | AppAction::ReverseSort => {
+                let (key, descending) = audio_player.file_manager.sort_order();
+                match action {
+                    AppAction::CycleSort => audio_player.file_manager.set_sort_order(key.next(), false),
+                    _ => audio_player.file_manager.set_sort_order(key, !descending),
                 }
+                browser.refilter(&mut audio_player.file_manager);
+                browser.file_explorer.jump_to(audio_player.file_manager.position());
             }
             AppAction::IncreaseVolume => audio_player.increase_volume_by(0.15),
             AppAction::DecreaseVolume => audio_player.decrease_volume_by(0.15),
+            AppAction::AddToQueue => {
+                for path in browser.selected_tracks(&audio_player.file_manager) {
+                    audio_player.file_manager.enqueue(path);
+                }
+            }
+            AppAction::PlayNext => {
+                for path in browser.selected_tracks(&audio_player.file_manager).into_iter().rev() {
+                    audio_player.file_manager.play_next(path);
+                }
+            }
+            AppAction::ToggleQueueView => {
+                browser.view = match browser.view{
+                    View::Queue => View::Files,
+                    _ => View::Queue,
+                };
+            }
+            // files, then the library by artist, genre and year
+            AppAction::CycleView => {
+                let grouping = match (browser.view, browser.library_view.grouping()) {
+                    (View::Library, Grouping::Artist) => Some(Grouping::Genre),
+                    (View::Library, Grouping::Genre) => Some(Grouping::Year),
+                    (View::Library, Grouping::Year) => None,
+                    _ => Some(Grouping::Artist),
+                };
+                match grouping {
+                    Some(grouping) => {
+                        browser.library_view.set_grouping(grouping, &mut audio_player.file_manager);
+                        browser.view = View::Library;
+                    }
+                    None => browser.view = View::Files,
+                }
+            }
+            AppAction::ToggleNode if browser.view == View::Library => browser.library_view.toggle_selected(),
+            AppAction::MoveSelection(n) => match browser.view{
+                View::Files => file_explorer.move_selection_by(n),
+                View::Queue => browser.queue_view.move_selection_by(n, audio_player.file_manager.queued().len()),
+                View::Library => browser.library_view.move_selection_by(n),
+                View::Directories => browser.directory_browser.move_selection_by(n),
+                View::Lyrics => browser.lyrics_view.move_selection_by(n),
+                View::Chapters => browser.chapter_view.move_selection_by(n),
+                View::Bookmarks => browser.bookmark_view.move_selection_by(n),
+                View::History => browser.history_view.move_selection_by(n),
+            }
+            AppAction::MovePage(n) if browser.organise_view.is_some() => {
+                let organise_view = browser.organise_view.as_ref().unwrap();
+                organise_view.scroll_by(n*organise_view.page_size() as i32);
+            }
+            AppAction::MovePage(n) => match browser.view{
+                View::Files => file_explorer.move_selection_by(n*file_explorer.page_size() as i32),
+                View::Queue => browser.queue_view.move_selection_by(n*browser.queue_view.page_size() as i32, audio_player.file_manager.queued().len()),
+                View::Library => browser.library_view.move_selection_by(n*browser.library_view.page_size() as i32),
+                View::Directories => browser.directory_browser.move_selection_by(n*browser.directory_browser.page_size() as i32),
+                View::Lyrics => browser.lyrics_view.move_selection_by(n*browser.lyrics_view.page_size() as i32),
+                View::Chapters => browser.chapter_view.move_selection_by(n*browser.chapter_view.page_size() as i32),
+                View::Bookmarks => browser.bookmark_view.move_selection_by(n*browser.bookmark_view.page_size() as i32),
+                View::History => browser.history_view.move_selection_by(n*browser.history_view.page_size() as i32),
+            }
+            AppAction::SelectFirst => match browser.view{
+                View::Files => file_explorer.select(0),
+                View::Queue => browser.queue_view.select(0, audio_player.file_manager.queued().len()),
+                View::Library => browser.library_view.select(0),
+                View::Directories => browser.directory_browser.select(0),
+                View::Lyrics => browser.lyrics_view.select(0),
+                View::Chapters => browser.chapter_view.select(0),
+                View::Bookmarks => browser.bookmark_view.select(0),
+                View::History => browser.history_view.select(0),
+            }
+            AppAction::SelectLast => match browser.view{
+                View::Files => file_explorer.select(usize::MAX),
+                View::Queue => browser.queue_view.select(usize::MAX, audio_player.file_manager.queued().len()),
+                View::Library => browser.library_view.select(usize::MAX),
+                View::Directories => browser.directory_browser.select(usize::MAX),
+                View::Lyrics => browser.lyrics_view.select(usize::MAX),
+                View::Chapters => browser.chapter_view.select(usize::MAX),
+                View::Bookmarks => browser.bookmark_view.select(usize::MAX),
+                View::History => browser.history_view.select(usize::MAX),
+            }
+            AppAction::ToggleDirectoryView => {
+                browser.view = match browser.view{
+                    View::Directories => View::Files,
+                    _ => View::Directories,
+                };
+            }
+            AppAction::ToggleLyricsView => {
+                browser.view = match browser.view{
+                    View::Lyrics => View::Files,
+                    _ => View::Lyrics,
+                };
+            }
+            AppAction::ToggleChapterView => {
+                browser.view = match browser.view{
+                    View::Chapters => View::Files,
+                    _ => View::Chapters,
+                };
+            }
+            AppAction::ToggleBookmarkView => {
+                browser.view = match browser.view{
+                    View::Bookmarks => View::Files,
+                    _ => View::Bookmarks,
+                };
+            }
+            AppAction::ToggleHistoryView => {
+                browser.view = match browser.view{
+                    View::History => View::Files,
+                    _ => View::History,
+                };
+            }
+            // the playing track, a queued one is not at the position in the list
+            AppAction::AddBookmark => match audio_player.get_current_path(){
+                Some(path) => browser.new_bookmark = Some(Bookmark{path,name:String::new(),time:audio_player.get_timestamp()}),
+                None => {
+                    status_line.set_message(String::from("Nothing is playing to bookmark"));
+                    browser.stop_typing();
+                }
+            }
+            AppAction::PlaySelected if browser.view == View::Chapters => {
+                if let Some(time) = browser.chapter_view.selected_time() {
+                    audio_player.set_timestamp(time);
+                }
+            }
+            AppAction::NextChapter => {
+                match browser.chapter_view.next_start(audio_player.get_timestamp()){
+                    Some(time) => audio_player.set_timestamp(time),
+                    None => status_line.set_message(String::from("No next chapter")),
+                }
+            }
+            AppAction::PrevChapter => {
+                match browser.chapter_view.prev_start(audio_player.get_timestamp()){
+                    Some(time) => audio_player.set_timestamp(time),
+                    None => status_line.set_message(String::from("No previous chapter")),
+                }
+            }
+            // seeks to the selected line, lines without a time stay where they are
+            AppAction::PlaySelected if browser.view == View::Lyrics => {
+                if let Some(time) = browser.lyrics_view.selected_time() {
+                    audio_player.set_timestamp(time);
+                }
+            }
+            AppAction::PlaySelected | AppAction::ParentDirectory | AppAction::ToggleFavourite if browser.view == View::Directories => {
+                App::match_directory_actions(action, &mut browser.directory_browser, status_line);
+            }
+            // a whole artist or album starts with its first track, the others are queued right after it
+            AppAction::PlaySelected => {
+                let mut tracks = browser.selected_tracks(&audio_player.file_manager).into_iter();
+                let position = tracks.next().and_then(|path| audio_player.file_manager.position_of(&path));
+                if position.is_some_and(|position| audio_player.set_track_at(position)) {
+                    for path in tracks.rev() {
+                        audio_player.file_manager.play_next(path);
+                    }
+                    browser.file_explorer.jump_to(audio_player.file_manager.position());
+                    if !audio_player.is_running() {
+                        audio_player.run().unwrap();
+                    } else if !audio_player.is_playing() {
+                        audio_player.play();
+                    }
+                }
+            }
+            AppAction::MoveQueued(n) if browser.view == View::Queue => {
+                let index = audio_player.file_manager.move_queued(browser.queue_view.selected(), n);
+                browser.queue_view.select(index, audio_player.file_manager.queued().len());
+            }
+            AppAction::RemoveQueued if browser.view == View::Queue => {
+                audio_player.file_manager.remove_queued(browser.queue_view.selected());
+            }
+            AppAction::RemoveQueued if browser.view == View::Bookmarks => {
+                if let Err(e) = browser.bookmark_view.remove_selected() {
+                    status_line.set_message(format!("Cannot remove the bookmark: {}", e));
+                }
+            }
+            AppAction::StartSearch => {
+                browser.view = View::Files;
+                browser.search = Some(Search::new(file_explorer.highlighted()));
+                browser.update_search(&mut audio_player.file_manager);
+            }
+            AppAction::EditTags => {
+                let mut tracks = browser.selected_tracks(&audio_player.file_manager);
+                let len = tracks.len();
+                // the tags of a cue track are lines of its sheet, which is not written
+                tracks.retain(|path| audio_player.file_manager.segment(path).is_none());
+                if tracks.is_empty() {
+                    status_line.set_message(match len{
+                        0 => String::from("No tracks to edit here"),
+                        _ => String::from("Tracks of a cue sheet cannot be edited, change the .cue file instead"),
+                    });
+                    browser.stop_typing();
+                } else {
+                    if tracks.len() != len {
+                        status_line.set_message(format!("Left out {} tracks of a cue sheet, change the .cue file for them", len-tracks.len()));
+                    }
+                    browser.tag_editor = Some(TagEditor::new(tracks, &mut audio_player.file_manager));
+                }
+            }
+            AppAction::ToggleMark if browser.view == View::Files => {
+                if let Some(position) = file_explorer.highlighted() {
+                    file_explorer.toggle_mark(audio_player.file_manager.path_at(position));
+                    file_explorer.move_selection_by(1);
+                }
+            }
+            AppAction::ClearMarks => file_explorer.clear_marks(),
+            AppAction::Organise => {
+                let tracks = browser.organise_tracks(&audio_player.file_manager);
+                if tracks.is_empty() {
+                    status_line.set_message(String::from("No tracks to organise here"));
+                    browser.stop_typing();
+                } else {
+                    // as tall as the list area of the other views
+                    let viewport_size = browser.queue_view.page_size();
+                    browser.organise_view = Some(OrganiseView::new(tracks, browser.organise_pattern.clone(), viewport_size, &mut audio_player.file_manager));
+                }
+            }
+            AppAction::UndoOrganise => match organise::undo() {
+                Ok(outcome) => browser.apply_moves(&outcome, &mut audio_player.file_manager, status_line),
+                Err(e) => status_line.set_message(format!("Cannot undo: {}", e)),
+            }
+            AppAction::TextInput(c) if browser.new_bookmark.is_some() => browser.new_bookmark.as_mut().unwrap().name.push(c),
+            AppAction::TextBackspace if browser.new_bookmark.is_some() => { browser.new_bookmark.as_mut().unwrap().name.pop(); }
+            AppAction::TextInput(c) if browser.new_playlist.is_some() => {
+                let playlist = browser.new_playlist.as_mut().unwrap();
+                playlist.name.push(c);
+                playlist.overwrite = false;
+            }
+            AppAction::TextBackspace if browser.new_playlist.is_some() => {
+                let playlist = browser.new_playlist.as_mut().unwrap();
+                playlist.name.pop();
+                playlist.overwrite = false;
+            }
+            AppAction::TextInput(c) if browser.tag_editor.is_some() => browser.tag_editor.as_mut().unwrap().input(c),
+            AppAction::TextBackspace if browser.tag_editor.is_some() => browser.tag_editor.as_mut().unwrap().backspace(),
+            AppAction::TextInput(c) if browser.organise_view.is_some() => browser.organise_view.as_mut().unwrap().input(c, &mut audio_player.file_manager),
+            AppAction::TextBackspace if browser.organise_view.is_some() => browser.organise_view.as_mut().unwrap().backspace(&mut audio_player.file_manager),
+            AppAction::TextInput(c) => {
+                if let Some(search) = browser.search.as_mut() {
+                    search.query.push(c);
+                }
+                browser.update_search(&mut audio_player.file_manager);
+            }
+            AppAction::TextBackspace => {
+                if let Some(search) = browser.search.as_mut() {
+                    search.query.pop();
+                }
+                browser.update_search(&mut audio_player.file_manager);
+            }
+            AppAction::TextNextField | AppAction::TextPrevField => {
+                let n = if matches!(action, AppAction::TextNextField) { 1 } else { -1 };
+                if let Some(tag_editor) = browser.tag_editor.as_mut() {
+                    tag_editor.move_field(n);
+                }
+                if let Some(organise_view) = browser.organise_view.as_ref() {
+                    organise_view.scroll_by(n);
+                }
+            }
+            // without a name the bookmark is named after the track and the time
+            AppAction::TextConfirm if browser.new_bookmark.is_some() => {
+                let mut bookmark = browser.new_bookmark.take().unwrap();
+                bookmark.name = bookmark.name.trim().to_string();
+                let time = graphics::duration_to_mmss(bookmark.time);
+                if bookmark.name.is_empty() {
+                    let file = bookmark.path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                    bookmark.name = format!("{} {}", file, time);
+                }
+                let name = bookmark.name.clone();
+                match browser.bookmark_view.add(bookmark){
+                    Ok(()) => status_line.set_message(format!("Bookmarked {} at {}", name, time)),
+                    Err(e) => status_line.set_message(format!("Cannot save the bookmark: {}", e)),
+                }
+            }
+            AppAction::TextCancel if browser.new_bookmark.is_some() => browser.new_bookmark = None,
+            // an existing file is only replaced after a second Enter, the prompt stays open until then
+            AppAction::TextConfirm if browser.new_playlist.is_some() => {
+                let mut playlist = browser.new_playlist.take().unwrap();
+                let location = playlist.location();
+                if playlist.name.trim().is_empty() {
+                    status_line.set_message(String::from("No playlist name, nothing saved"));
+                } else if location.exists() && !playlist.overwrite {
+                    playlist.overwrite = true;
+                    browser.new_playlist = Some(playlist);
+                    browser.is_typing.store(true, atomic::Ordering::SeqCst);
+                } else {
+                    App::save_playlist(audio_player, &location, status_line);
+                }
+            }
+            AppAction::TextCancel if browser.new_playlist.is_some() => browser.new_playlist = None,
+            AppAction::TextConfirm if browser.tag_editor.is_some() => {
+                browser.save_tags(&mut audio_player.file_manager, status_line);
+            }
+            AppAction::TextCancel if browser.tag_editor.is_some() => browser.tag_editor = None,
+            AppAction::TextConfirm if browser.organise_view.is_some() => {
+                let organise_view = browser.organise_view.take().unwrap();
+                browser.organise_pattern = organise_view.pattern().to_string();
+                if let Some(error) = organise_view.error() {
+                    status_line.set_message(format!("Nothing moved, {}", error));
+                } else if !organise_view.moves().is_empty() {
+                    match organise::apply(organise_view.moves()) {
+                        Ok(outcome) => browser.apply_moves(&outcome, &mut audio_player.file_manager, status_line),
+                        Err(e) => status_line.set_message(format!("Nothing moved, {}", e)),
+                    }
+                }
+            }
+            AppAction::TextCancel if browser.organise_view.is_some() => browser.organise_view = None,
+            // back to the whole list with the cursor on the chosen match, n/N keep cycling through the matches
+            AppAction::TextConfirm => {
+                let chosen = browser.file_explorer.highlighted();
+                let mut origin = None;
+                if let Some(search) = browser.search.as_mut() {
+                    search.is_typing = false;
+                    origin = search.origin;
+                }
+                browser.refilter(&mut audio_player.file_manager);
+                if let Some(position) = chosen.or(origin) {
+                    browser.file_explorer.select_nearest(position);
+                }
+            }
+            AppAction::TextCancel => {
+                let origin = browser.search.take().and_then(|search| search.origin);
+                browser.refilter(&mut audio_player.file_manager);
+                browser.file_explorer.set_query("");
+                if let Some(position) = origin {
+                    browser.file_explorer.select_nearest(position);
+                }
+            }
+            AppAction::CycleRatingFilter => {
+                let highlighted = browser.file_explorer.highlighted();
+                browser.rating_filter = browser.rating_filter.next();
+                browser.view = View::Files;
+                browser.refilter(&mut audio_player.file_manager);
+                if let Some(position) = highlighted {
+                    browser.file_explorer.select_nearest(position);
+                }
+            }
+            AppAction::NextMatch | AppAction::PrevMatch if browser.view == View::Files => {
+                let forward = matches!(action, AppAction::NextMatch);
+                let from = file_explorer.highlighted().unwrap_or(0);
+                if let Some(position) = browser.search.as_ref().and_then(|search| search.next_match(&mut audio_player.file_manager, from, forward)) {
+                    file_explorer.select_position(position);
+                }
+            }
             _=>(),
         }
     }
 
+    fn apply_library_changes(watcher:&LibraryWatcher,audio_player:&mut AudioPlayer,browser:&mut Browser){
+        let changes = watcher.changes();
+        if changes.is_empty() { return }
+        let mut list_changed = false;
+        for change in changes{
+            list_changed |= audio_player.file_manager.apply_change(&change);
+            if let LibraryChange::Modified(path) = &change {
+                browser.file_explorer.invalidate(path);
+            }
+        }
+        if list_changed {
+            browser.file_explorer.set_files(audio_player.file_manager.file_paths.len());
+            browser.refilter(&mut audio_player.file_manager);
+            browser.file_explorer.follow(audio_player.file_manager.position());
+        }
+        // tags may have changed as well, the tree is cheap to build from the library
+        if browser.view == View::Library {
+            browser.library_view.rebuild(&mut audio_player.file_manager);
+        }
+    }
+
     pub fn run(&mut self) -> Result<()> {
         // min screen size
         if self.size.0>80 && self.size.1>5 {
@@ -201,21 +1275,87 @@ impl App {
                 Hide,
             ).unwrap();
             enable_raw_mode().unwrap();
+            // a panic must not leave the terminal in raw mode on the alternate screen
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                disable_raw_mode().ok();
+                execute!(stdout(), LeaveAlternateScreen, Show).ok();
+                default_hook(info);
+            }));
 
             let square = Square::new(relative_size(0.8, self.size.0), relative_size(0.7, self.size.1));
             let mut volume_display = graphics::VolumeDisplay::new(relative_size(0.05, self.size.0), 3.0);
+            let mut level_meter = graphics::LevelMeter::new(relative_size(0.1, self.size.0));
             let mut time_slider = graphics::TimeSlider::new(relative_size(0.5, self.size.0));
+            let mut status_line = graphics::StatusLine::new(self.size.0 - 2);
+            // the right column below the mode labels, down to the time slider
+            let art_position = (relative_size(0.8, self.size.0)+2, relative_size(0.03, self.size.1)+4);
+            let mut album_art = AlbumArt::new(self.art_protocol,
+                                              self.size.0.saturating_sub(art_position.0+1),
+                                              relative_size(0.85, self.size.1).saturating_sub(art_position.1+1));
             let (tx, rx) = mpsc::channel();
+            let is_typing = Arc::new(AtomicBool::new(false));
+            let key_thread_typing = is_typing.clone();
             let key_thread = spawn(|| {
-                async_std::task::block_on(App::process_key_events(tx));
+                async_std::task::block_on(App::process_key_events(tx, key_thread_typing));
             });
-            if let Ok( mut audio_player) =  AudioPlayer::new(self.path.as_path()){
-                let mut file_explorer = FileExplorer::new(audio_player.file_manager.file_paths.len(), square.height as usize - 2);
+            let mut directory_browser = DirectoryBrowser::new(&self.start_dir(), self.scan_options.clone(), square.height as usize - 2);
+            let mut audio_player = self.load_file_manager(&mut status_line).and_then(AudioPlayer::new);
+            // nothing to play, another folder can be picked instead of restarting
+            while let Err(e) = audio_player.as_ref() {
+                status_line.set_message(e.to_string());
+                match self.pick_directory(&rx, &square, &mut directory_browser, &mut status_line)? {
+                    Some(dir) => {
+                        self.paths = vec![dir];
+                        audio_player = self.load_file_manager(&mut status_line).and_then(AudioPlayer::new);
+                    }
+                    None => break,
+                }
+            }
+            if let Ok( mut audio_player) = audio_player {
+                let mut browser = Browser{
+                    view:View::Files,
+                    file_explorer:FileExplorer::new(audio_player.file_manager.file_paths.len(), square.height as usize - 2),
+                    queue_view:QueueView::new(square.height as usize - 2),
+                    library_view:LibraryView::new(square.height as usize - 2),
+                    directory_browser,
+                    lyrics_view:LyricsView::new(square.height as usize - 2),
+                    chapter_view:ChapterView::new(square.height as usize - 2),
+                    bookmark_view:BookmarkView::new(Bookmarks::open(&self.bookmarks_location), square.height as usize - 2),
+                    history_view:HistoryView::new(History::open(&history::default_location()), square.height as usize - 2),
+                    new_bookmark:None,
+                    new_playlist:None,
+                    search:None,
+                    tag_editor:None,
+                    organise_view:None,
+                    organise_pattern:String::from(organise::DEFAULT_PATTERN),
+                    rating_filter:RatingFilter::All,
+                    is_typing,
+                };
+                browser.file_explorer.set_columns(self.columns.clone());
+                // broken files at the start of the list were already skipped
+                browser.file_explorer.follow(audio_player.file_manager.position());
+                // without a watcher the list is just not updated while the player runs
+                let mut watcher = LibraryWatcher::new(audio_player.file_manager.roots()).ok();
+                if let Some(error) = browser.bookmark_view.bookmarks().error() {
+                    status_line.set_message(error.to_string());
+                }
+                if let Some(error) = audio_player.file_manager.library_error() {
+                    status_line.set_message(error.to_string());
+                }
+                self.resume(&mut audio_player, &mut browser, &mut status_line);
                 let mut clock = FramerateClock::new(15);
+                // a closed terminal never sends Esc, so the state is also saved every now and then
+                let mut resume_saved = Instant::now();
+                let mut recorder = Recorder::default();
                 loop {
-                    match audio_player.get_track_meta() {
-                        Some(meta) => {
-                            time_slider.set_duration(Some(meta.duration));
+                    if resume_saved.elapsed()>RESUME_SAVE_INTERVAL {
+                        self.save_resume_state(&audio_player);
+                        resume_saved = Instant::now();
+                    }
+                    match audio_player.get_duration() {
+                        Some(duration) => {
+                            time_slider.set_duration(Some(duration));
                             time_slider.set_timestamp(audio_player.get_timestamp());
                         }
                         None => {
@@ -223,14 +1363,33 @@ impl App {
                             time_slider.set_timestamp(Duration::from_secs(0));
                         }
                     }
+                    // every started track ends up in the history, with the time it was heard
+                    if recorder.is_new(audio_player.plays_started()) {
+                        let path = audio_player.get_current_path().unwrap_or_else(|| audio_player.file_manager.get_current());
+                        let info = audio_player.file_manager.get_metadata(&path).unwrap_or_default();
+                        if let Some(play) = recorder.start(audio_player.plays_started(), path, &info) {
+                            App::record_play(play, &mut browser, &mut status_line);
+                        }
+                    }
+                    recorder.tick(audio_player.is_running() && audio_player.is_playing());
+                    for (path, error) in audio_player.take_skipped() {
+                        status_line.set_message(format!("Skipped {}: {}", path.display(), error));
+                    }
 
-                    self.stdout.queue(Clear(ClearType::All))?;
+                    album_art.clear_screen(&mut self.stdout, art_position.0, art_position.1, self.size)?;
                     // draw interface
                     square.draw(&mut self.stdout, 0, 0)?;
 
                     volume_display.set_volume(audio_player.get_volume());
                     volume_display.draw(&mut self.stdout, relative_size(0.01, self.size.0), relative_size(0.85, self.size.1))?;
 
+                    if audio_player.is_playing() {
+                        level_meter.set_levels(audio_player.get_levels());
+                    } else {
+                        level_meter.set_levels(Levels::default());
+                    }
+                    level_meter.draw(&mut self.stdout, relative_size(0.01, self.size.0), relative_size(0.85, self.size.1) + 1)?;
+
                     time_slider.draw(&mut self.stdout, relative_size(0.2, self.size.0), relative_size(0.85, self.size.1))?;
 
                     let mut state = style(graphics::graphic_symbols::PAUSE).red();
@@ -245,31 +1404,109 @@ impl App {
                     }
                     draw_text(&mut self.stdout, is_shuffled_text, relative_size(0.8, self.size.0)+2, relative_size(0.03, self.size.1))?;
 
-                    file_explorer.draw(&mut self.stdout, &mut audio_player.file_manager, 1, 1, relative_size(0.8, self.size.0 - 6));
+                    let repeat = audio_player.file_manager.repeat();
+                    let mut repeat_text = style(repeat.label()).dark_grey();
+                    if repeat != RepeatMode::Off{
+                        repeat_text = repeat_text.white();
+                    }
+                    draw_text(&mut self.stdout, repeat_text, relative_size(0.8, self.size.0)+2, relative_size(0.03, self.size.1)+1)?;
+
+                    let (sort_key, descending) = audio_player.file_manager.sort_order();
+                    let arrow = if descending { graphics::graphic_symbols::SORT_DESCENDING } else { graphics::graphic_symbols::SORT_ASCENDING };
+                    let mut sort_text = style(format!("{} {}", sort_key.label(), arrow)).dark_grey();
+                    if sort_key != SortKey::Default || descending {
+                        sort_text = sort_text.white();
+                    }
+                    draw_text(&mut self.stdout, sort_text, relative_size(0.8, self.size.0)+2, relative_size(0.03, self.size.1)+2)?;
+
+                    let mut rating_text = style(browser.rating_filter.label()).dark_grey();
+                    if browser.rating_filter != RatingFilter::All {
+                        rating_text = rating_text.white();
+                    }
+                    draw_text(&mut self.stdout, rating_text, relative_size(0.8, self.size.0)+2, relative_size(0.03, self.size.1)+3)?;
+
+                    // the chapters mark the time slider and are needed for seeking, so they follow the track in every view
+                    if audio_player.get_current_path().is_some_and(|path| browser.chapter_view.set_track(&path)) {
+                        time_slider.set_markers(browser.chapter_view.starts());
+                    }
+                    browser.chapter_view.update(audio_player.get_timestamp());
+                    // the playing track, the position in the list stays put while a queued track plays.
+                    // the tracks of a cue sheet show the cover of their audio file
+                    if let Some(path) = audio_player.get_current_path() {
+                        album_art.set_track(&audio_player.file_manager.audio_file(&path));
+                    }
+                    album_art.draw(&mut self.stdout, art_position.0, art_position.1)?;
+
+                    // lyrics are only read while they are shown
+                    if let Some(path) = audio_player.get_current_path().filter(|_| browser.view == View::Lyrics) {
+                        browser.lyrics_view.set_track(&path);
+                        browser.lyrics_view.update(audio_player.get_timestamp());
+                    }
+                    browser.draw(&mut self.stdout, &mut audio_player.file_manager, 1, 1, relative_size(0.8, self.size.0 - 6));
+                    browser.draw_search(&mut self.stdout, 1, self.size.1 - 2)?;
+                    status_line.draw(&mut self.stdout, 1, self.size.1 - 1)?;
                     self.stdout.flush()?;
 
                     // if sample is None then the current track has finished
-                    if let None = audio_player.get_current_sample() {
-                        if audio_player.current_track_is_active() {
-                            if audio_player.file_manager.tracks_left() != 0 {
-                                audio_player.set_next_track_in_dir();
-                                file_explorer.move_down();
-                            } else { audio_player.pause() }
+                    if audio_player.get_current_sample().is_none() && audio_player.current_track_is_active() {
+                        if let Some(play) = recorder.finish(true) {
+                            App::record_play(play, &mut browser, &mut status_line);
                         }
+                        if audio_player.file_manager.repeat() == RepeatMode::One {
+                            if !audio_player.replay_track() { audio_player.pause() }
+                        }
+                        else if audio_player.file_manager.has_next() && audio_player.set_next_track_in_dir() {
+                            browser.file_explorer.follow(audio_player.file_manager.position());
+                        } else { audio_player.pause() }
+                    }
+                    if let Some(watcher) = watcher.as_ref() {
+                        App::apply_library_changes(watcher, &mut audio_player, &mut browser);
                     }
                     // get input
                     if let Ok(action) = rx.try_recv() {
                         match action {
                             AppAction::Exit => break,
-                            _ => App::match_key_actions(action,&mut audio_player,&mut file_explorer)
+                            AppAction::SavePlaylist => browser.new_playlist = Some(self.playlist_prompt()),
+                            AppAction::SetRating(stars) => self.rate(|rating| Rating{stars, ..rating}, &mut audio_player, &mut browser, &mut status_line),
+                            // marked tracks all become favourites unless they already are
+                            AppAction::ToggleFavouriteTrack => {
+                                let tracks = browser.selected_tracks(&audio_player.file_manager);
+                                let is_favourite = !tracks.iter().all(|path| audio_player.file_manager.rating(path).is_favourite);
+                                self.rate(|rating| Rating{is_favourite, ..rating}, &mut audio_player, &mut browser, &mut status_line);
+                            }
+                            AppAction::PlaySelected if browser.view == View::Bookmarks => {
+                                if let Some(bookmark) = browser.bookmark_view.selected().cloned() {
+                                    let message = format!("Jumped to {}", bookmark.name);
+                                    if self.play_from(&bookmark.path, bookmark.time, message, &mut audio_player, &mut browser, &mut status_line) {
+                                        watcher = LibraryWatcher::new(audio_player.file_manager.roots()).ok();
+                                    }
+                                }
+                            }
+                            AppAction::PlaySelected if browser.view == View::History => {
+                                if let Some(path) = browser.history_view.selected().map(|play| play.path.clone()) {
+                                    let message = format!("Playing {}", path.display());
+                                    if self.play_from(&path, Duration::from_secs(0), message, &mut audio_player, &mut browser, &mut status_line) {
+                                        watcher = LibraryWatcher::new(audio_player.file_manager.roots()).ok();
+                                    }
+                                }
+                            }
+                            AppAction::LoadDirectory if browser.view == View::Directories => {
+                                let dir = browser.directory_browser.selected().unwrap_or_else(|| browser.directory_browser.current_dir().to_path_buf());
+                                if self.load_directory(dir, &mut audio_player, &mut browser, &mut status_line) {
+                                    watcher = LibraryWatcher::new(audio_player.file_manager.roots()).ok();
+                                }
+                            }
+                            _ => App::match_key_actions(action,&mut audio_player,&mut browser,&mut status_line)
                         }
                     }
                     clock.sleep_if_needed();
                 }
+                self.save_resume_state(&audio_player);
+                if let Some(play) = recorder.finish(false) {
+                    App::record_play(play, &mut browser, &mut status_line);
+                }
                 audio_player.stop();
-            }
-            else{
-                println!("\rPress Esc");
+                audio_player.file_manager.save_library().ok();
             }
             key_thread.join().unwrap();
             disable_raw_mode().unwrap();
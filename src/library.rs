@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use serde::{Serialize, Deserialize};
+use anyhow::anyhow;
+use mp3_metadata::Genre;
+use crate::id3;
+
+// bump when TrackInfo or LibraryEntry change, the entries of older indexes are then rebuilt
+const INDEX_VERSION:u32 = 7;
+// from this version on the ratings come right after the version, so they outlive the entries
+const FIRST_RATED_VERSION:u32 = 7;
+// bump when Rating changes, an index with ratings of another version is left alone
+const RATINGS_VERSION:u32 = 1;
+const INDEX_FILE:&str = "library.idx";
+
+fn absolute(path:&Path) -> PathBuf{
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// paths as OsString, which unlike PathBuf also takes names that are not UTF-8
+mod os_paths{
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    pub fn serialize<V:Serialize, S:Serializer>(map:&HashMap<PathBuf, V>, serializer:S) -> Result<S::Ok, S::Error>{
+        serializer.collect_map(map.iter().map(|(path, value)| (path.as_os_str(), value)))
+    }
+    pub fn deserialize<'de, V:Deserialize<'de>, D:Deserializer<'de>>(deserializer:D) -> Result<HashMap<PathBuf, V>, D::Error>{
+        let map = HashMap::<OsString, V>::deserialize(deserializer)?;
+        Ok(map.into_iter().map(|(path, value)| (PathBuf::from(path), value)).collect())
+    }
+}
+
+// id3v1 fields are padded with zeros or spaces
+fn clean(text:&str) -> String{
+    text.trim_matches(|c:char| c == char::from(0) || c.is_whitespace()).to_string()
+}
+
+// "3/12" -> 3
+fn leading_number(text:&str) -> Option<u32>{
+    text.split('/').next().and_then(|number| number.trim().parse().ok())
+}
+
+// "(17)" and "17" are id3v1 genre numbers, "(17)Rock" refines one with a name
+fn genre_name(genre:&Genre) -> String{
+    match genre{
+        Genre::Unknown => String::new(),
+        Genre::Something(text) => {
+            let text = clean(text);
+            let number = text.strip_prefix('(').and_then(|rest| rest.split_once(')'));
+            match number{
+                Some((_, name)) if !name.is_empty() => clean(name),
+                Some((number, _)) => number.parse::<u8>().map(|number| genre_name(&Genre::from(number))).unwrap_or(text),
+                None => text,
+            }
+        }
+        // the variant names are the genre names in CamelCase
+        genre => {
+            let mut name = String::new();
+            for c in format!("{:?}", genre).chars(){
+                if c.is_uppercase() && !name.is_empty() { name.push(' ') }
+                name.push(c);
+            }
+            name
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct TrackInfo{
+    pub title:String,
+    pub artist:String,
+    pub album:String,
+    pub track_number:Option<u32>,
+    pub year:Option<u32>,
+    pub genre:String,
+    pub album_artist:String,
+    pub disc_number:Option<u32>,
+    pub comment:String,
+    pub duration:Duration,
+    // from the POPM frame of the tag unless the track was rated in the player
+    pub rating:Rating,
+}
+
+// 0 to 5 stars, 0 is unrated
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct Rating{
+    pub stars:u8,
+    pub is_favourite:bool,
+}
+impl TrackInfo{
+    // the id3v2 tag wins over the id3v1 tag at the end of the file
+    pub fn read(path:&Path) -> Result<TrackInfo, anyhow::Error>{
+        let meta = mp3_metadata::read_from_file(path).map_err(|e| anyhow!("invalid mp3 ({:?})", e))?;
+        let mut info = TrackInfo{duration:meta.duration, ..TrackInfo::default()};
+        if let Some(tag) = meta.tag.as_ref(){
+            info.title = clean(&tag.title);
+            info.artist = clean(&tag.artist);
+            info.album = clean(&tag.album);
+            info.year = Some(tag.year as u32).filter(|year| *year != 0);
+            info.genre = genre_name(&tag.genre);
+            info.comment = clean(&tag.comment);
+        }
+        // a broken id3v2 tag still leaves the v1 tags and the duration
+        if let Ok(Some(tag)) = id3::Tag::read(path){
+            info.merge_tag(&tag);
+        }
+        Ok(info)
+    }
+    fn merge_tag(&mut self, tag:&id3::Tag){
+        let text = |id:&str| tag.text(id).map(|text| clean(&text)).filter(|text| !text.is_empty());
+        if let Some(title) = text("TIT2") { self.title = title }
+        if let Some(artist) = text("TPE1") { self.artist = artist }
+        if let Some(album_artist) = text("TPE2") { self.album_artist = album_artist }
+        if let Some(album) = text("TALB") { self.album = album }
+        if let Some(track) = text("TRCK") { self.track_number = leading_number(&track).or(self.track_number) }
+        if let Some(disc) = text("TPOS") { self.disc_number = leading_number(&disc) }
+        // TYER in id3v2.3, the recording date "2004-05-01" in id3v2.4
+        if let Some(year) = text("TDRC").or_else(|| text("TYER")) {
+            self.year = year.get(..4).and_then(|year| year.parse().ok()).or(self.year);
+        }
+        if let Some(genre) = text("TCON") {
+            // v2.4 lists several genres, the first one is used
+            let first = genre.split("; ").next().unwrap_or_default();
+            self.genre = genre_name(&Genre::from(first));
+        }
+        if let Some(comment) = tag.comment().map(|comment| clean(&comment)).filter(|comment| !comment.is_empty()) {
+            self.comment = comment;
+        }
+        if let Some(stars) = tag.rating() {
+            self.rating.stars = stars;
+        }
+    }
+    // fills the fields that are missing in the tags, e.g. from a playlist entry
+    pub fn with_fallback(mut self, fallback:&TrackInfo) -> TrackInfo{
+        if self.title.is_empty() { self.title = fallback.title.clone() }
+        if self.artist.is_empty() { self.artist = fallback.artist.clone() }
+        if self.album.is_empty() { self.album = fallback.album.clone() }
+        if self.track_number.is_none() { self.track_number = fallback.track_number }
+        if self.year.is_none() { self.year = fallback.year }
+        if self.genre.is_empty() { self.genre = fallback.genre.clone() }
+        if self.album_artist.is_empty() { self.album_artist = fallback.album_artist.clone() }
+        if self.disc_number.is_none() { self.disc_number = fallback.disc_number }
+        if self.comment.is_empty() { self.comment = fallback.comment.clone() }
+        if self.duration == Duration::from_secs(0) { self.duration = fallback.duration }
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LibraryEntry{
+    mtime:SystemTime,
+    size:u64,
+    info:TrackInfo,
+}
+
+// given in the player, unlike the entries they cannot be read again from the files
+#[derive(Serialize, Deserialize)]
+struct Ratings{
+    version:u32,
+    // by absolute path, cue tracks included, and kept when a file changes or is gone for a while
+    #[serde(with = "os_paths")]
+    ratings:HashMap<PathBuf, Rating>,
+}
+
+#[derive(Deserialize)]
+struct Entries(#[serde(with = "os_paths")] HashMap<PathBuf, LibraryEntry>);
+
+// the file is the version, the ratings and the entries, read one after the other
+#[derive(Serialize)]
+struct Index{
+    version:u32,
+    ratings:Ratings,
+    #[serde(with = "os_paths")]
+    entries:HashMap<PathBuf, LibraryEntry>,
+}
+impl Index{
+    fn empty() -> Index{
+        Index{version:INDEX_VERSION,ratings:Ratings{version:RATINGS_VERSION,ratings:HashMap::new()},entries:HashMap::new()}
+    }
+}
+
+// tags of every known file, keyed by path and invalidated by mtime and size
+pub struct Library{
+    location:Option<PathBuf>,
+    index:Index,
+    is_dirty:bool,
+    // an index with ratings that cannot be read is never overwritten
+    error:Option<String>,
+}
+impl Library{
+    pub fn default_location() -> Option<PathBuf>{
+        dirs::data_dir().map(|dir| dir.join("rplayer").join(INDEX_FILE))
+    }
+    // opens the index in the XDG data dir, a missing or outdated index gives an empty library
+    pub fn open() -> Library{
+        match Library::default_location(){
+            Some(location) => Library::load(&location),
+            None => Library{location:None,index:Index::empty(),is_dirty:false,error:None},
+        }
+    }
+    // the entries are only a cache and start over when anything is wrong with them, the ratings are not
+    pub fn load(location:&Path) -> Library{
+        let mut library = Library{location:Some(location.to_path_buf()),index:Index::empty(),is_dirty:false,error:None};
+        let mut reader = match File::open(location){
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => return library,
+            Err(e) => {
+                library.error = Some(format!("{} cannot be read: {}", location.display(), e));
+                return library
+            }
+        };
+        // older indexes had no ratings of their own
+        let version = match bincode::deserialize_from::<_, u32>(&mut reader){
+            Ok(version) if version >= FIRST_RATED_VERSION => version,
+            _ => return library,
+        };
+        match bincode::deserialize_from::<_, Ratings>(&mut reader){
+            Ok(ratings) if ratings.version == RATINGS_VERSION => library.index.ratings = ratings,
+            Ok(_) => library.error = Some(format!("the ratings in {} were written by another version of rplayer", location.display())),
+            Err(e) => library.error = Some(format!("the ratings in {} cannot be read: {}", location.display(), e)),
+        }
+        if library.error.is_none() && version == INDEX_VERSION {
+            if let Ok(entries) = bincode::deserialize_from::<_, Entries>(&mut reader) {
+                library.index.entries = entries.0;
+            }
+        }
+        library
+    }
+    pub fn error(&self) -> Option<&str>{
+        self.error.as_deref()
+    }
+    pub fn save(&mut self) -> Result<(), anyhow::Error>{
+        if let Some(error) = self.error.as_ref() {
+            return Err(anyhow!("{}", error))
+        }
+        if !self.is_dirty { return Ok(()) }
+        let location = self.location.as_ref().ok_or_else(|| anyhow!("no data directory for the library index"))?;
+        if let Some(dir) = location.parent(){
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temporary file first so an interrupted save keeps the old index
+        let tmp_location = location.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_location)?);
+        bincode::serialize_into(&mut writer, &self.index)?;
+        writer.flush()?;
+        fs::rename(&tmp_location, location)?;
+        self.is_dirty = false;
+        Ok(())
+    }
+    fn is_up_to_date(&self, path:&Path, mtime:SystemTime, size:u64) -> bool{
+        self.index.entries.get(path).is_some_and(|entry| entry.mtime == mtime && entry.size == size)
+    }
+    fn refresh(&mut self, path:&Path) -> Result<&TrackInfo, anyhow::Error>{
+        let metadata = fs::metadata(path)?;
+        let mtime = metadata.modified()?;
+        if !self.is_up_to_date(path, mtime, metadata.len()){
+            let info = TrackInfo::read(path)?;
+            self.index.entries.insert(path.to_path_buf(), LibraryEntry{mtime,size:metadata.len(),info});
+            self.is_dirty = true;
+        }
+        Ok(&self.index.entries[path].info)
+    }
+    // re-reads tags of new and changed files
+    pub fn update(&mut self, paths:&[PathBuf]){
+        for path in paths{
+            self.refresh(path).ok();
+        }
+    }
+    // forgets files that no longer exist, this stats every entry so it is only done on a full scan
+    pub fn prune(&mut self){
+        let len = self.index.entries.len();
+        self.index.entries.retain(|path, _| path.exists());
+        self.is_dirty |= len != self.index.entries.len();
+    }
+    pub fn get(&self, path:&Path) -> Option<&TrackInfo>{
+        self.index.entries.get(path).map(|entry| &entry.info)
+    }
+    pub fn modified(&self, path:&Path) -> Option<SystemTime>{
+        self.index.entries.get(path).map(|entry| entry.mtime)
+    }
+    pub fn info(&mut self, path:&Path) -> Result<TrackInfo, anyhow::Error>{
+        if let Some(info) = self.get(path){
+            return Ok(info.clone())
+        }
+        self.refresh(path).cloned()
+    }
+    // the rating given in the player, None leaves the one in the tag
+    pub fn rating(&self, path:&Path) -> Option<Rating>{
+        self.index.ratings.ratings.get(&absolute(path)).copied()
+    }
+    pub fn set_rating(&mut self, path:&Path, rating:Rating){
+        self.index.ratings.ratings.insert(absolute(path), rating);
+        self.is_dirty = true;
+    }
+    // the ratings of a moved file or directory go along with it, returns whether there were any
+    pub fn rename_ratings(&mut self, from:&Path, to:&Path) -> bool{
+        let (from, to) = (absolute(from), absolute(to));
+        let ratings = &mut self.index.ratings.ratings;
+        let moved:Vec<PathBuf> = ratings.keys().filter(|path| path.starts_with(&from)).cloned().collect();
+        for path in moved.iter(){
+            let rating = ratings.remove(path).unwrap();
+            let rest = path.strip_prefix(&from).unwrap();
+            ratings.insert(if rest.as_os_str().is_empty() { to.clone() } else { to.join(rest) }, rating);
+            self.is_dirty = true;
+        }
+        !moved.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn location(name:&str) -> PathBuf{
+        std::env::temp_dir().join(format!("rplayer-library-test-{}-{}.idx", name, std::process::id()))
+    }
+    fn rated(stars:u8) -> Rating{
+        Rating{stars,is_favourite:false}
+    }
+
+    #[test]
+    fn ratings_read_back(){
+        let location = location("read-back");
+        let mut library = Library::load(&location);
+        library.set_rating(Path::new("/music/song.mp3"), Rating{stars:4,is_favourite:true});
+        library.save().unwrap();
+        let read = Library::load(&location);
+        fs::remove_file(&location).unwrap();
+        assert!(read.error().is_none());
+        assert!(read.rating(Path::new("/music/song.mp3")) == Some(Rating{stars:4,is_favourite:true}));
+        assert!(read.rating(Path::new("/music/other.mp3")).is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_are_kept(){
+        use std::os::unix::ffi::OsStrExt;
+        let location = location("not-utf8");
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/music/caf\xe9.mp3"));
+        let mut library = Library::load(&location);
+        library.set_rating(path, rated(3));
+        library.index.entries.insert(path.to_path_buf(), LibraryEntry{mtime:SystemTime::UNIX_EPOCH,size:1,info:TrackInfo::default()});
+        library.save().unwrap();
+        let read = Library::load(&location);
+        fs::remove_file(&location).unwrap();
+        assert!(read.rating(path) == Some(rated(3)));
+        assert!(read.get(path).is_some());
+    }
+
+    #[test]
+    fn ratings_outlive_another_index_version(){
+        let location = location("index-version");
+        let mut library = Library::load(&location);
+        library.set_rating(Path::new("/music/song.mp3"), rated(2));
+        library.index.entries.insert(PathBuf::from("/music/song.mp3"), LibraryEntry{mtime:SystemTime::UNIX_EPOCH,size:1,info:TrackInfo::default()});
+        library.save().unwrap();
+        let mut bytes = fs::read(&location).unwrap();
+        bytes[..4].copy_from_slice(&(INDEX_VERSION+1).to_le_bytes());
+        fs::write(&location, &bytes).unwrap();
+        let read = Library::load(&location);
+        fs::remove_file(&location).unwrap();
+        assert!(read.error().is_none());
+        assert!(read.rating(Path::new("/music/song.mp3")) == Some(rated(2)));
+        assert!(read.get(Path::new("/music/song.mp3")).is_none());
+    }
+
+    #[test]
+    fn indexes_before_the_ratings_start_over(){
+        let location = location("old-index");
+        fs::write(&location, bincode::serialize(&(FIRST_RATED_VERSION-1, 0u64)).unwrap()).unwrap();
+        let mut library = Library::load(&location);
+        library.set_rating(Path::new("/music/song.mp3"), rated(1));
+        let result = library.save();
+        fs::remove_file(&location).unwrap();
+        assert!(library.error().is_none());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn ratings_of_another_version_are_not_overwritten(){
+        let location = location("ratings-version");
+        let bytes = bincode::serialize(&(INDEX_VERSION, RATINGS_VERSION+1, 0u64)).unwrap();
+        fs::write(&location, &bytes).unwrap();
+        let mut library = Library::load(&location);
+        library.set_rating(Path::new("/music/song.mp3"), rated(1));
+        let result = library.save();
+        let written = fs::read(&location).unwrap();
+        fs::remove_file(&location).unwrap();
+        assert!(library.error().is_some());
+        assert!(result.is_err());
+        assert_eq!(written, bytes);
+    }
+
+    #[test]
+    fn ratings_follow_a_moved_directory(){
+        let mut library = Library::load(&location("rename"));
+        library.set_rating(Path::new("/music/album/song.mp3"), rated(2));
+        library.set_rating(Path::new("/music/albums/song.mp3"), rated(5));
+        assert!(library.rename_ratings(Path::new("/music/album"), Path::new("/music/moved")));
+        assert!(!library.rename_ratings(Path::new("/music/album"), Path::new("/music/moved")));
+        assert!(library.rating(Path::new("/music/album/song.mp3")).is_none());
+        assert!(library.rating(Path::new("/music/moved/song.mp3")) == Some(rated(2)));
+        assert!(library.rating(Path::new("/music/albums/song.mp3")) == Some(rated(5)));
+    }
+}
@@ -0,0 +1,231 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Stdout;
+use std::path::PathBuf;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::file_manager::FileManager;
+use crate::library::TrackInfo;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Grouping{
+    Artist,
+    Genre,
+    Year,
+}
+impl Grouping{
+    fn label(self) -> &'static str{
+        match self{
+            Grouping::Artist => "Artists",
+            Grouping::Genre => "Genres",
+            Grouping::Year => "Years",
+        }
+    }
+    // tracks without the tag are collected under "Unknown ...", which sorts last
+    fn key(self, info:&TrackInfo) -> (bool, String){
+        let key = match self{
+            Grouping::Artist => info.artist.clone(),
+            Grouping::Genre => info.genre.clone(),
+            // the newest years first
+            Grouping::Year => info.year.map(|year| format!("{:05}", 99999-year)).unwrap_or_default(),
+        };
+        (key.is_empty(), key.to_lowercase())
+    }
+    fn name(self, info:&TrackInfo) -> String{
+        let name = match self{
+            Grouping::Artist => info.artist.clone(),
+            Grouping::Genre => info.genre.clone(),
+            Grouping::Year => info.year.map(|year| year.to_string()).unwrap_or_default(),
+        };
+        if !name.is_empty() { return name }
+        match self{
+            Grouping::Artist => String::from("Unknown artist"),
+            Grouping::Genre => String::from("Unknown genre"),
+            Grouping::Year => String::from("Unknown year"),
+        }
+    }
+}
+
+struct Track{
+    path:PathBuf,
+    track_number:Option<u32>,
+    name:String,
+}
+struct Album{
+    name:String,
+    tracks:Vec<Track>,
+}
+struct Group{
+    name:String,
+    albums:BTreeMap<(bool, String), Album>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Node{
+    Group(usize),
+    Album(usize, usize),
+    Track(usize, usize, usize),
+}
+
+// the tracks as a tree of groups (artist, genre or year), albums and tracks
+pub struct LibraryView{
+    grouping:Grouping,
+    groups:Vec<Group>,
+    expanded:HashSet<Node>,
+    rows:Vec<Node>,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl LibraryView{
+    pub fn new(viewport_size:usize) -> LibraryView{
+        LibraryView{grouping:Grouping::Artist,groups:Vec::new(),expanded:HashSet::new(),rows:Vec::new(),viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    pub fn grouping(&self) -> Grouping{
+        self.grouping
+    }
+    pub fn set_grouping(&mut self, grouping:Grouping, file_manager:&mut FileManager){
+        self.grouping = grouping;
+        self.expanded.clear();
+        self.rebuild(file_manager);
+        self.select(0);
+    }
+    // reads the tags of every file again, the expanded nodes stay open where they still exist
+    pub fn rebuild(&mut self, file_manager:&mut FileManager){
+        let expanded:HashSet<(String, Option<String>)> = self.expanded.iter().map(|node| self.node_names(node)).collect();
+        let mut groups:BTreeMap<(bool, String), Group> = BTreeMap::new();
+        for path in file_manager.file_paths.clone(){
+            let info = file_manager.get_metadata(&path).unwrap_or_default();
+            let group = groups.entry(self.grouping.key(&info)).or_insert_with(|| Group{name:self.grouping.name(&info),albums:BTreeMap::new()});
+            let album_key = (info.album.is_empty(), info.album.to_lowercase());
+            let album = group.albums.entry(album_key).or_insert_with(|| Album{
+                name:if info.album.is_empty() { String::from("Unknown album") } else { info.album.clone() },
+                tracks:Vec::new(),
+            });
+            let name = if info.title.is_empty() { path.file_name().unwrap_or_default().to_string_lossy().into_owned() } else { info.title.clone() };
+            album.tracks.push(Track{path,track_number:info.track_number,name});
+        }
+        self.groups = groups.into_values().collect();
+        for group in self.groups.iter_mut(){
+            for album in group.albums.values_mut(){
+                album.tracks.sort_by(|a, b| a.track_number.unwrap_or(u32::MAX).cmp(&b.track_number.unwrap_or(u32::MAX)).then_with(|| a.path.cmp(&b.path)));
+            }
+        }
+        self.expanded.clear();
+        for (group_index, group) in self.groups.iter().enumerate(){
+            if expanded.contains(&(group.name.clone(), None)){
+                self.expanded.insert(Node::Group(group_index));
+            }
+            for (album_index, album) in group.albums.values().enumerate(){
+                if expanded.contains(&(group.name.clone(), Some(album.name.clone()))){
+                    self.expanded.insert(Node::Album(group_index, album_index));
+                }
+            }
+        }
+        self.flatten();
+    }
+    fn node_names(&self, node:&Node) -> (String, Option<String>){
+        match node{
+            Node::Group(group) => (self.groups[*group].name.clone(), None),
+            Node::Album(group, album) | Node::Track(group, album, _) => {
+                let group = &self.groups[*group];
+                (group.name.clone(), group.albums.values().nth(*album).map(|album| album.name.clone()))
+            }
+        }
+    }
+    fn flatten(&mut self){
+        self.rows.clear();
+        for (group_index, group) in self.groups.iter().enumerate(){
+            self.rows.push(Node::Group(group_index));
+            if !self.expanded.contains(&Node::Group(group_index)) { continue }
+            for (album_index, album) in group.albums.values().enumerate(){
+                self.rows.push(Node::Album(group_index, album_index));
+                if !self.expanded.contains(&Node::Album(group_index, album_index)) { continue }
+                self.rows.extend((0..album.tracks.len()).map(|track| Node::Track(group_index, album_index, track)));
+            }
+        }
+        self.select(self.selected_index.get());
+    }
+    // opens or closes the selected artist, genre, year or album
+    pub fn toggle_selected(&mut self){
+        let node = match self.rows.get(self.selected_index.get()){
+            Some(Node::Track(..)) | None => return,
+            Some(node) => node.clone(),
+        };
+        if !self.expanded.remove(&node){
+            self.expanded.insert(node);
+        }
+        self.flatten();
+    }
+    // every track below the selected node, in album and track order
+    pub fn selected_tracks(&self) -> Vec<PathBuf>{
+        let group = |group:usize| &self.groups[group];
+        let album = |group_index:usize, album:usize| group(group_index).albums.values().nth(album).unwrap();
+        match self.rows.get(self.selected_index.get()){
+            Some(Node::Group(group_index)) => group(*group_index).albums.values()
+                .flat_map(|album| album.tracks.iter().map(|track| track.path.clone()))
+                .collect(),
+            Some(Node::Album(group, album_index)) => album(*group, *album_index).tracks.iter().map(|track| track.path.clone()).collect(),
+            Some(Node::Track(group, album_index, track)) => vec![album(*group, *album_index).tracks[*track].path.clone()],
+            None => Vec::new(),
+        }
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.rows.len().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, file_manager:&mut FileManager, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let header = style(graphics::set_text_width(format!("{} / Albums / Tracks", self.grouping.label()), row_width)).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
+        y+=1;
+        if self.rows.is_empty(){
+            graphics::draw_text(stdout,style(String::from("No tracks")).dark_grey(),x,y).unwrap();
+            return
+        }
+        let playing = file_manager.get_current();
+        let start_index = self.start_index.get();
+        for (index,node) in self.rows.iter().enumerate().skip(start_index).take(self.viewport_size){
+            let expander = |node:&Node| if self.expanded.contains(node) { "▾" } else { "▸" };
+            let (text, is_playing) = match node{
+                Node::Group(group) => {
+                    let group = &self.groups[*group];
+                    let tracks:usize = group.albums.values().map(|album| album.tracks.len()).sum();
+                    (format!("{} {} ({})", expander(node), group.name, tracks), false)
+                }
+                Node::Album(group, album) => {
+                    let album = self.groups[*group].albums.values().nth(*album).unwrap();
+                    (format!("  {} {} ({})", expander(node), album.name, album.tracks.len()), false)
+                }
+                Node::Track(group, album, track) => {
+                    let track = &self.groups[*group].albums.values().nth(*album).unwrap().tracks[*track];
+                    let number = track.track_number.map(|number| format!("{:02}. ", number)).unwrap_or_default();
+                    let is_playing = track.path == playing;
+                    let marker = if is_playing { graphics::graphic_symbols::NOW_PLAYING } else { " " };
+                    (format!("    {}{}{}", marker, number, track.name), is_playing)
+                }
+            };
+            let mut description = style(graphics::set_text_width(text, row_width));
+            if is_playing{
+                description = description.green();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
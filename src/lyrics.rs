@@ -0,0 +1,193 @@
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use crate::id3;
+
+pub struct Line{
+    // None for lyrics without timing
+    pub time:Option<Duration>,
+    pub text:String,
+}
+
+pub struct Lyrics{
+    pub lines:Vec<Line>,
+}
+impl Lyrics{
+    // a sidecar .lrc wins over the synchronised and then the plain lyrics in the tag
+    pub fn load(path:&Path) -> Option<Lyrics>{
+        let sidecar = ["lrc", "LRC"].iter().map(|extension| path.with_extension(extension)).find(|sidecar| sidecar.is_file());
+        if let Some(text) = sidecar.and_then(|sidecar| fs::read(sidecar).ok()) {
+            // lrc files are utf-8 or latin-1, sometimes with a byte order mark
+            let text = match String::from_utf8(text){
+                Ok(text) => text,
+                Err(e) => e.into_bytes().iter().map(|byte| *byte as char).collect(),
+            };
+            return Some(Lyrics::parse_lrc(text.trim_start_matches('\u{feff}'))).filter(|lyrics| !lyrics.lines.is_empty())
+        }
+        let tag = id3::Tag::read(path).ok().flatten()?;
+        if let Some(synced) = tag.synced_lyrics() {
+            return Some(Lyrics::from_synced(synced))
+        }
+        // plain lyrics are often lrc text pasted into the tag
+        tag.lyrics().map(|text| Lyrics::parse_lrc(&text)).filter(|lyrics| !lyrics.lines.is_empty())
+    }
+    // SYLT entries can be single syllables, a new line starts with a line break
+    fn from_synced(entries:Vec<(u32, String)>) -> Lyrics{
+        let is_by_syllable = entries.iter().any(|(_, text)| text.starts_with('\n') || text.starts_with('\r'));
+        let mut lines:Vec<Line> = Vec::new();
+        for (time, text) in entries{
+            let is_new_line = !is_by_syllable || lines.is_empty() || text.starts_with('\n') || text.starts_with('\r');
+            let text = text.trim_start_matches(['\r', '\n']);
+            match lines.last_mut(){
+                Some(line) if !is_new_line => line.text.push_str(text),
+                _ => lines.push(Line{time:Some(Duration::from_millis(time as u64)),text:text.to_string()}),
+            }
+        }
+        Lyrics{lines}
+    }
+    // "[01:02.50]text", several times can share a line, [offset:+500] makes the lines show up earlier
+    pub fn parse_lrc(text:&str) -> Lyrics{
+        let mut offset = 0i64;
+        let mut lines = Vec::new();
+        for line in text.lines(){
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            let mut has_tags = false;
+            while let Some(tag) = rest.strip_prefix('[').and_then(|tag| tag.split_once(']')){
+                let (tag, after) = tag;
+                match parse_time(tag){
+                    Some(time) => times.push(time),
+                    None => {
+                        if let Some(value) = tag.strip_prefix("offset:") {
+                            offset = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                }
+                has_tags = true;
+                rest = after;
+            }
+            // lines with only [ar:...] and the like are metadata
+            if has_tags && times.is_empty() { continue }
+            let text = strip_word_times(rest.trim());
+            if times.is_empty() {
+                if !text.is_empty() || !lines.is_empty() {
+                    lines.push(Line{time:None,text});
+                }
+                continue
+            }
+            for time in times{
+                let time = Duration::from_millis((time as i64 - offset).max(0) as u64);
+                lines.push(Line{time:Some(time),text:text.clone()});
+            }
+        }
+        if lines.iter().any(|line| line.time.is_some()) {
+            lines.retain(|line| line.time.is_some());
+            lines.sort_by_key(|line| line.time);
+        }
+        Lyrics{lines}
+    }
+    pub fn is_synced(&self) -> bool{
+        self.lines.iter().any(|line| line.time.is_some())
+    }
+    // the last line that started at the timestamp
+    pub fn current_line(&self, timestamp:Duration) -> Option<usize>{
+        self.lines.iter().rposition(|line| line.time.is_some_and(|time| time<=timestamp))
+    }
+}
+
+// "mm:ss", "mm:ss.xx" or "mm:ss.xxx" in milliseconds
+fn parse_time(tag:&str) -> Option<u64>{
+    let (minutes, seconds) = tag.split_once(':')?;
+    let minutes:u64 = minutes.trim().parse().ok()?;
+    let (seconds, fraction) = seconds.split_once(['.', ':']).unwrap_or((seconds, ""));
+    let seconds:u64 = seconds.parse().ok()?;
+    let fraction = match fraction.len(){
+        0 => 0,
+        1..=3 => fraction.parse::<u64>().ok()? * 10u64.pow(3-fraction.len() as u32),
+        _ => return None,
+    };
+    Some((minutes*60+seconds)*1000+fraction)
+}
+
+// enhanced lrc times single words with <mm:ss.xx>
+fn strip_word_times(text:&str) -> String{
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<'){
+        match rest[start..].find('>').filter(|end| parse_time(&rest[start+1..start+end]).is_some()){
+            Some(end) => {
+                result.push_str(&rest[..start]);
+                rest = &rest[start+end+1..];
+            }
+            None => {
+                result.push_str(&rest[..=start]);
+                rest = &rest[start+1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn times(lyrics:&Lyrics) -> Vec<Option<u64>>{
+        lyrics.lines.iter().map(|line| line.time.map(|time| time.as_millis() as u64)).collect()
+    }
+
+    #[test]
+    fn parse_time_formats(){
+        assert_eq!(parse_time("01:02"), Some(62000));
+        assert_eq!(parse_time("01:02.5"), Some(62500));
+        assert_eq!(parse_time("01:02.50"), Some(62500));
+        assert_eq!(parse_time("01:02.505"), Some(62505));
+        assert_eq!(parse_time("01:02:50"), Some(62500));
+        assert_eq!(parse_time("01:02.5055"), None);
+        assert_eq!(parse_time("ar:Artist"), None);
+    }
+
+    #[test]
+    fn lines_are_sorted_and_can_share_times(){
+        let lyrics = Lyrics::parse_lrc("[ar:Artist]\n[00:10.00][00:30.00]Chorus\n[00:20.00]Verse\n");
+        assert_eq!(times(&lyrics), [Some(10000), Some(20000), Some(30000)]);
+        let texts:Vec<&str> = lyrics.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, ["Chorus", "Verse", "Chorus"]);
+        assert!(lyrics.is_synced());
+        assert_eq!(lyrics.current_line(Duration::from_secs(25)), Some(1));
+        assert_eq!(lyrics.current_line(Duration::from_secs(5)), None);
+    }
+
+    #[test]
+    fn offsets_move_the_lines(){
+        // a positive offset shows the lines earlier, no line starts before zero
+        let lyrics = Lyrics::parse_lrc("[offset:+500]\n[00:00.20]First\n[00:02.00]Second\n");
+        assert_eq!(times(&lyrics), [Some(0), Some(1500)]);
+        let lyrics = Lyrics::parse_lrc("[offset:-250]\n[00:02.00]Second\n");
+        assert_eq!(times(&lyrics), [Some(2250)]);
+    }
+
+    #[test]
+    fn word_times_are_stripped(){
+        let lyrics = Lyrics::parse_lrc("[00:01.00]<00:01.00>One <00:01.50>two <not a time>\n");
+        assert_eq!(lyrics.lines[0].text, "One two <not a time>");
+    }
+
+    #[test]
+    fn plain_lyrics_keep_their_lines(){
+        let lyrics = Lyrics::parse_lrc("\nFirst\n\nSecond\n");
+        assert_eq!(times(&lyrics), [None, None, None]);
+        assert!(!lyrics.is_synced());
+        assert_eq!(lyrics.lines[2].text, "Second");
+    }
+
+    #[test]
+    fn synced_syllables_are_joined_into_lines(){
+        let entries = vec![(0, String::from("One ")), (500, String::from("two")), (1000, String::from("\nThree"))];
+        let lyrics = Lyrics::from_synced(entries);
+        assert_eq!(times(&lyrics), [Some(0), Some(1000)]);
+        assert_eq!(lyrics.lines[0].text, "One two");
+        assert_eq!(lyrics.lines[1].text, "Three");
+    }
+}
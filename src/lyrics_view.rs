@@ -0,0 +1,99 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::lyrics::Lyrics;
+
+// the lyrics of the playing track, the line being sung stays in the middle while the cursor is on it
+pub struct LyricsView{
+    track:Option<PathBuf>,
+    lyrics:Option<Lyrics>,
+    current:Cell<Option<usize>>,
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl LyricsView{
+    pub fn new(viewport_size:usize) -> LyricsView{
+        LyricsView{track:None,lyrics:None,current:Cell::new(None),viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    // loads the lyrics when the track changed
+    pub fn set_track(&mut self, path:&Path){
+        if self.track.as_deref() == Some(path) { return }
+        self.track = Some(path.to_path_buf());
+        self.lyrics = Lyrics::load(path);
+        self.current.set(None);
+        self.select(0);
+    }
+    fn rows(&self) -> usize{
+        self.lyrics.as_ref().map_or(0, |lyrics| lyrics.lines.len())
+    }
+    // moves the highlight to the line at the timestamp, the cursor goes along unless it was moved away
+    pub fn update(&self, timestamp:Duration){
+        let current = self.lyrics.as_ref().and_then(|lyrics| lyrics.current_line(timestamp));
+        if current == self.current.get() { return }
+        let is_following = self.current.get().unwrap_or(0) == self.selected_index.get();
+        self.current.set(current);
+        if let (true, Some(current)) = (is_following, current) {
+            self.selected_index.set(current);
+            self.start_index.set(current.saturating_sub(self.viewport_size/2).min(self.rows().saturating_sub(self.viewport_size)));
+        }
+    }
+    // where the selected line starts, unsynchronised lyrics have no times
+    pub fn selected_time(&self) -> Option<Duration>{
+        self.lyrics.as_ref()?.lines.get(self.selected_index.get())?.time
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn select(&self, index:usize){
+        let index = index.min(self.rows().saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32){
+        self.select((self.selected_index.get() as i32 + n).max(0) as usize);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let lyrics = match self.lyrics.as_ref(){
+            Some(lyrics) => lyrics,
+            None => {
+                let header = style(graphics::set_text_width(String::from("Lyrics"), row_width)).on_dark_blue();
+                graphics::draw_text(stdout,header,x,y).unwrap();
+                let empty = style(String::from("No lyrics, they are read from a .lrc file next to the track or from its tags")).dark_grey();
+                graphics::draw_text(stdout,empty,x,y+1).unwrap();
+                return
+            }
+        };
+        let title = if lyrics.is_synced() { "Lyrics, Enter jumps to the selected line" } else { "Lyrics" };
+        let header = style(graphics::set_text_width(String::from(title), row_width)).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
+        y+=1;
+        let start_index = self.start_index.get();
+        for (index,line) in lyrics.lines.iter().enumerate().skip(start_index).take(self.viewport_size){
+            let is_current = Some(index) == self.current.get();
+            let marker = if is_current { graphics::graphic_symbols::NOW_PLAYING } else { " " };
+            let mut description = style(graphics::set_text_width(format!("{} {}", marker, line.text), row_width));
+            if is_current{
+                description = description.green();
+            }
+            // the lines that were already sung
+            else if self.current.get().is_some_and(|current| index<current){
+                description = description.dark_grey();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
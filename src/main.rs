@@ -1,8 +1,34 @@
 use rplayer::App;
-use std::path::PathBuf;
+use rplayer::file_manager::{FileManager, ScanOptions};
+use rplayer::file_explorer::Column;
+use rplayer::organise::{self, Pattern};
+use rplayer::album_art::Protocol;
+use rplayer::resume::ResumeState;
+use rplayer::history::{self, History};
+use rplayer::library::Library;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 fn help(){
-    println!("Usage:\n player [path]");
+    println!("Usage:\n player [options] [path]...\n player organise [--pattern pattern] [--dry-run] [options] [path]...\n player organise --undo\n player history [--json] [file]");
+    println!(" path is a directory with mp3 files or a single .m3u/.m3u8/.pls/.xspf playlist, without one the last session is resumed");
+    println!(" organise moves the files according to their tags, the default pattern is {}", organise::DEFAULT_PATTERN);
+    println!(" pattern fields: title, artist, album-artist, album, track, disc, year, genre, filename, {{track:02}} pads with zeros");
+    println!(" history exports every play as CSV, or as JSON with --json, to the file or to the terminal");
+    println!("Options:");
+    println!(" --hidden - Include hidden files and directories");
+    println!(" --no-recursive - Only scan the top level of each path");
+    println!(" --ignore [pattern] - Skip files and directories matching a glob pattern");
+    println!(" --columns [list] - Explorer columns, e.g. filename,title,artist,duration");
+    println!(" --art [half-blocks|sixel|kitty|off] - How the album art is drawn, detected from the terminal by default");
+    println!(" --bookmarks [file] - The JSON file with the bookmarks, e.g. one shared next to the recordings");
+    println!(" --no-resume - Start from the first track instead of where the last session stopped");
+    println!(" --write-ratings - Also write the ratings to the POPM frame of the files");
+    println!(" --pattern [pattern] - Where organise moves the files, relative to the scanned directory");
+    println!(" --dry-run - Only list what organise would move");
+    println!(" --undo - Move the files of the last organise back");
+    println!(" --json - Export the history as JSON instead of CSV");
     println!("Shortcuts:");
     println!(" Ctrl + Left/Right arrow - Move timestamp");
     println!(" Left/Right arrow - Set previous/next track");
@@ -10,22 +36,198 @@ fn help(){
     println!(" Space - Toggle pause");
     println!(" Up/Down arrow - Control volume");
     println!(" S - shuffle");
+    println!(" R - cycle repeat mode: off, all, one");
+    println!(" O - cycle the sort order: default, filename, title, artist, album, track number, year, duration, date modified");
+    println!(" Shift + O - reverse the sort order");
+    println!(" W - save the current order as a playlist");
+    println!(" E - add the highlighted tracks to the queue");
+    println!(" P - play the highlighted tracks next");
+    println!(" Q - toggle the queue view");
+    println!(" V - cycle the list view: files, artists, genres, years");
+    println!(" Tab - open or close the highlighted artist, genre, year or album");
+    println!(" F - toggle the folder view");
+    println!(" Y - toggle the lyrics view, Enter jumps to the selected line");
+    println!(" C - toggle the chapter view, Enter jumps to the selected chapter");
+    println!(" ]/[ - jump to the next/previous chapter");
+    println!(" I - bookmark the current position, Enter saves it with the typed name");
+    println!(" Shift + I - toggle the bookmark view, Enter jumps to the selected bookmark, X removes it");
+    println!(" H - toggle the recently played view, Enter plays the selected track again");
+    println!(" 0-5 - rate the highlighted or marked tracks with 0 to 5 stars");
+    println!(" Shift + F - add or remove the highlighted or marked tracks as favourites");
+    println!(" Shift + S - cycle the rating filter: all, favourites, at least 1 to 5 stars");
+    println!(" Backspace - go to the parent folder");
+    println!(" L - load the highlighted folder");
+    println!(" B - add or remove the highlighted folder as a favourite");
+    println!(" J/K - move the highlight down/up");
+    println!(" PageUp/PageDown - move the highlight by a page");
+    println!(" Home/End - move the highlight to the first/last row");
+    println!(" Enter - play the highlighted tracks");
+    println!(" / - search by filename, title, artist or album");
+    println!(" N / Shift + N - jump to the next/previous search match");
+    println!(" Shift + J/K - move the selected queue entry down/up");
+    println!(" X/Delete - remove the selected queue entry");
+    println!(" T - edit the tags of the highlighted or marked tracks");
+    println!(" M - mark or unmark the highlighted track");
+    println!(" Shift + M - clear the marks");
+    println!(" G - organise the marked tracks or the whole list by their tags");
+    println!(" Shift + U - undo the last organise");
     println!(" Esc - close player");
 }
 
+// lists the moves first, conflicting files are never moved
+fn organise(paths:&[PathBuf], scan_options:&ScanOptions, pattern:&str, is_dry_run:bool) -> Result<(), anyhow::Error>{
+    let pattern = Pattern::parse(pattern)?;
+    let mut file_manager = FileManager::from_roots(paths, scan_options)?;
+    let tracks = file_manager.file_paths.clone();
+    let moves = organise::plan(&mut file_manager, &tracks, &pattern);
+    file_manager.save_library().ok();
+    for planned in moves.iter(){
+        match planned.conflict.as_ref(){
+            Some(conflict) => println!("! {} -> {} ({})", planned.from.display(), planned.to.display(), conflict),
+            None => println!("  {} -> {}", planned.from.display(), planned.to.display()),
+        }
+    }
+    let conflicts = moves.iter().filter(|planned| planned.conflict.is_some()).count();
+    println!("{} files to move, {} conflicts, {} already in place or unreadable", moves.len()-conflicts, conflicts, tracks.len()-moves.len());
+    if is_dry_run || moves.len() == conflicts { return Ok(()) }
+    let outcome = organise::apply(&moves)?;
+    print_outcome(&outcome);
+    move_ratings(&outcome);
+    println!("Undo with: player organise --undo");
+    Ok(())
+}
+// to stdout without a file, so it can be piped
+fn export_history(output:Option<&Path>, is_json:bool) -> Result<(), anyhow::Error>{
+    let history = History::open(&history::default_location());
+    let mut writer:Box<dyn Write> = match output{
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match is_json{
+        true => history.write_json(&mut writer)?,
+        false => history.write_csv(&mut writer)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+// the ratings follow the moved files like they do in the player
+fn move_ratings(outcome:&organise::Outcome){
+    let mut library = Library::open();
+    for (from, to) in outcome.moved.iter(){
+        library.rename_ratings(from, to);
+    }
+    if let Err(e) = library.save() {
+        println!("Cannot move the ratings: {}", e);
+    }
+}
+fn print_outcome(outcome:&organise::Outcome){
+    for error in outcome.errors.iter(){
+        println!("Failed: {}", error);
+    }
+    println!("Moved {} files", outcome.moved.len());
+}
+
 fn main() {
 
-    let args:Vec<String>= std::env::args().collect();
-    match args.len(){
-        1 => help(),
-        2 =>{
-            let mut app = App::new(PathBuf::from(args[1].clone()));
-            app.run().unwrap();
+    let mut args = std::env::args().skip(1).peekable();
+    let mut paths = Vec::new();
+    let mut scan_options = ScanOptions::default();
+    let mut columns = None;
+    let mut art_protocol = None;
+    let is_organise = args.next_if(|arg| arg == "organise").is_some();
+    let is_history = !is_organise && args.next_if(|arg| arg == "history").is_some();
+    let mut is_json = false;
+    let mut pattern = String::from(organise::DEFAULT_PATTERN);
+    let mut is_dry_run = false;
+    let mut is_undo = false;
+    let mut is_resume = true;
+    let mut bookmarks_location = None;
+    let mut write_ratings = false;
+    while let Some(arg) = args.next(){
+        match arg.as_str(){
+            "--pattern" if is_organise => match args.next(){
+                Some(next) => pattern = next,
+                None => { println!("--pattern expects a pattern"); return }
+            }
+            "--dry-run" if is_organise => is_dry_run = true,
+            "--undo" if is_organise => is_undo = true,
+            "--json" if is_history => is_json = true,
+            "--no-resume" => is_resume = false,
+            "--write-ratings" => write_ratings = true,
+            "--bookmarks" => match args.next(){
+                Some(next) => bookmarks_location = Some(PathBuf::from(next)),
+                None => { println!("--bookmarks expects a file"); return }
+            }
+            "--hidden" => scan_options.include_hidden = true,
+            "--no-recursive" => scan_options.recursive = false,
+            "--ignore" => {
+                match args.next().map(|pattern| scan_options.add_ignore_pattern(&pattern)){
+                    Some(Ok(())) => (),
+                    Some(Err(e)) => { println!("Invalid ignore pattern: {}", e); return }
+                    None => { println!("--ignore expects a pattern"); return }
+                }
+            }
+            "--columns" => {
+                let parsed:Option<Option<Vec<Column>>> = args.next().map(|list| list.split(',').map(Column::parse).collect());
+                match parsed{
+                    Some(Some(parsed)) if !parsed.is_empty() => columns = Some(parsed),
+                    Some(_) => { println!("Unknown column, use filename, title, artist, album-artist, album, track, disc, year, genre, comment, duration, plays, last-played or rating"); return }
+                    None => { println!("--columns expects a comma separated list"); return }
+                }
+            }
+            "--art" => {
+                match args.next().map(|name| Protocol::parse(&name)){
+                    Some(Some(protocol)) => art_protocol = Some(protocol),
+                    Some(None) => { println!("Unknown album art protocol, use half-blocks, sixel, kitty or off"); return }
+                    None => { println!("--art expects a protocol"); return }
+                }
+            }
+            _ => paths.push(PathBuf::from(arg)),
         }
-        _ =>{
-            println!("Too many arguments")
+    }
+    if is_history{
+        if let Err(e) = export_history(paths.first().map(PathBuf::as_path), is_json){
+            println!("Cannot export the history: {}", e);
         }
+        return
     }
-
+    if is_undo{
+        match organise::undo(){
+            Ok(outcome) => {
+                print_outcome(&outcome);
+                move_ratings(&outcome);
+            }
+            Err(e) => println!("Cannot undo: {}", e),
+        }
+        return
+    }
+    let resume_state = if is_resume && !is_organise { ResumeState::load() } else { None };
+    if paths.is_empty(){
+        match resume_state.as_ref(){
+            Some(state) => paths = state.paths.clone(),
+            None => { help(); return }
+        }
+    }
+    if is_organise{
+        if let Err(e) = organise(&paths, &scan_options, &pattern, is_dry_run){
+            println!("Cannot organise: {}", e);
+        }
+        return
+    }
+    let mut app = App::new(paths, scan_options);
+    if let Some(columns) = columns{
+        app.set_columns(columns);
+    }
+    if let Some(protocol) = art_protocol{
+        app.set_art_protocol(protocol);
+    }
+    if let Some(location) = bookmarks_location{
+        app.set_bookmarks_location(location);
+    }
+    app.set_write_ratings(write_ratings);
+    if let Some(state) = resume_state{
+        app.set_resume_state(state);
+    }
+    app.run().unwrap();
 
 }
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use anyhow::anyhow;
+use crate::file_manager::{is_mp3, FileManager};
+use crate::library::TrackInfo;
+
+pub const DEFAULT_PATTERN:&str = "{artist}/{album}/{track:02} - {title}.mp3";
+const JOURNAL_FILE:&str = "organise_journal";
+// bump when JournalEntry changes, an older journal can then no longer be undone
+const JOURNAL_VERSION:u32 = 2;
+
+pub fn journal_location() -> Option<PathBuf>{
+    dirs::data_dir().map(|dir| dir.join("rplayer").join(JOURNAL_FILE))
+}
+
+#[derive(Clone, Copy)]
+enum Field{
+    Title,
+    Artist,
+    AlbumArtist,
+    Album,
+    TrackNumber,
+    DiscNumber,
+    Year,
+    Genre,
+    Filename,
+}
+impl Field{
+    fn parse(name:&str) -> Option<Field>{
+        match name{
+            "title" => Some(Field::Title),
+            "artist" => Some(Field::Artist),
+            "album-artist" => Some(Field::AlbumArtist),
+            "album" => Some(Field::Album),
+            "track" => Some(Field::TrackNumber),
+            "disc" => Some(Field::DiscNumber),
+            "year" => Some(Field::Year),
+            "genre" => Some(Field::Genre),
+            "filename" => Some(Field::Filename),
+            _ => None,
+        }
+    }
+    // missing text tags get a placeholder so the folders stay readable, missing numbers are left out
+    fn text(self, path:&Path, info:&TrackInfo, width:usize) -> String{
+        let number = |number:Option<u32>| number.map(|number| format!("{:0width$}", number, width = width)).unwrap_or_default();
+        let text = |text:&str, unknown:&str| if text.is_empty() { unknown.to_string() } else { text.to_string() };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        match self{
+            Field::Title => text(&info.title, &stem),
+            Field::Artist => text(&info.artist, "Unknown artist"),
+            // compilations are usually tagged with the album artist only
+            Field::AlbumArtist => text(if info.album_artist.is_empty() { &info.artist } else { &info.album_artist }, "Unknown artist"),
+            Field::Album => text(&info.album, "Unknown album"),
+            Field::TrackNumber => number(info.track_number),
+            Field::DiscNumber => number(info.disc_number),
+            Field::Year => number(info.year),
+            Field::Genre => text(&info.genre, "Unknown genre"),
+            Field::Filename => stem,
+        }
+    }
+}
+
+enum Part{
+    Text(String),
+    // the width pads numbers with zeros, {track:02}
+    Field(Field, usize),
+}
+
+// a path relative to the scanned directory, with {field} or {field:0N} replaced by tags
+pub struct Pattern{
+    parts:Vec<Part>,
+}
+impl Pattern{
+    pub fn parse(pattern:&str) -> Result<Pattern, anyhow::Error>{
+        let mut parts = Vec::new();
+        let mut rest = pattern;
+        while let Some(start) = rest.find('{'){
+            if start>0 { parts.push(Part::Text(rest[..start].to_string())) }
+            let end = rest[start..].find('}').ok_or_else(|| anyhow!("missing }} after {}", &rest[start..]))? + start;
+            let (name, width) = match rest[start+1..end].split_once(':'){
+                Some((name, width)) => (name, width.parse().map_err(|_| anyhow!("{} is not a width", width))?),
+                None => (&rest[start+1..end], 0),
+            };
+            let field = Field::parse(name).ok_or_else(|| anyhow!("unknown field {{{}}}", name))?;
+            parts.push(Part::Field(field, width));
+            rest = &rest[end+1..];
+        }
+        if !rest.is_empty() { parts.push(Part::Text(rest.to_string())) }
+        if parts.is_empty() { return Err(anyhow!("the pattern is empty")) }
+        Ok(Pattern{parts})
+    }
+    // tags never add folders, separators and characters other systems reject are replaced
+    fn render(&self, path:&Path, info:&TrackInfo) -> PathBuf{
+        let mut text = String::new();
+        for part in self.parts.iter(){
+            match part{
+                Part::Text(part) => text.push_str(part),
+                Part::Field(field, width) => text.extend(field.text(path, info, *width).chars()
+                    .map(|c| if c.is_control() || "/\\<>:\"|?*".contains(c) { '_' } else { c })),
+            }
+        }
+        let mut target:PathBuf = text.split('/').filter(|component| !component.is_empty()).map(|component| {
+            // a missing number leaves its separator behind, "{track:02} - {title}" gives "- title"
+            let component = component.trim_start_matches(|c:char| c.is_whitespace() || c == '-').trim_end().trim_end_matches('.');
+            if component.is_empty() { "_" } else { component }
+        }).collect();
+        if !is_mp3(&target){
+            let name = format!("{}.{}", target.file_name().unwrap_or_default().to_string_lossy(), path.extension().unwrap_or_default().to_string_lossy());
+            target.set_file_name(name);
+        }
+        target
+    }
+}
+
+pub struct Move{
+    pub root:PathBuf,
+    pub from:PathBuf,
+    pub to:PathBuf,
+    // why the file stays where it is
+    pub conflict:Option<String>,
+}
+
+// where every file would go, files that are already in place are left out
+pub fn plan(file_manager:&mut FileManager, paths:&[PathBuf], pattern:&Pattern) -> Vec<Move>{
+    let roots = file_manager.roots().to_vec();
+    // the tracks of a cue sheet share one file and stay where it is
+    let mut moves:Vec<Move> = paths.iter().filter_map(|from| {
+        if file_manager.segment(from).is_some() { return None }
+        // the deepest root the file is in, files of a playlist stay in their own folder
+        let root = roots.iter().filter(|root| from.starts_with(root)).max_by_key(|root| root.components().count()).cloned()
+            .or_else(|| from.parent().map(Path::to_path_buf))?;
+        let info = file_manager.get_metadata(from).ok()?;
+        let to = root.join(pattern.render(from, &info));
+        Some(Move{root,from:from.clone(),to,conflict:None}).filter(|planned| planned.to != planned.from)
+    }).collect();
+    let mut targets:HashMap<PathBuf, usize> = HashMap::new();
+    for planned in moves.iter(){
+        *targets.entry(planned.to.clone()).or_default() += 1;
+    }
+    for planned in moves.iter_mut(){
+        // a rename that only changes the case is the same file on case insensitive systems
+        let is_same_file = planned.to.canonicalize().ok().is_some_and(|to| planned.from.canonicalize().ok() == Some(to));
+        if targets[&planned.to]>1 {
+            planned.conflict = Some(format!("{} files get this name", targets[&planned.to]));
+        }
+        else if planned.to.exists() && !is_same_file {
+            planned.conflict = Some(String::from("already exists"));
+        }
+    }
+    moves
+}
+
+// the files that were moved and what went wrong with the others
+pub struct Outcome{
+    pub moved:Vec<(PathBuf, PathBuf)>,
+    pub errors:Vec<String>,
+}
+
+fn move_file(from:&Path, to:&Path, root:&Path) -> Result<(), anyhow::Error>{
+    if to.exists() && to.canonicalize()? != from.canonicalize()? {
+        return Err(anyhow!("{} already exists", to.display()))
+    }
+    if let Some(dir) = to.parent(){
+        fs::create_dir_all(dir)?;
+    }
+    fs::rename(from, to)?;
+    // folders that only held moved files go away with them, up to the scanned directory
+    for dir in from.ancestors().skip(1).take_while(|dir| dir.starts_with(root) && *dir != root){
+        if fs::remove_dir(dir).is_err() { break }
+    }
+    Ok(())
+}
+
+// one move, the names are kept as they are on disk so any file name can be moved back
+#[derive(Serialize, Deserialize)]
+struct JournalEntry{
+    from:OsString,
+    to:OsString,
+    root:OsString,
+}
+
+// the version followed by the moves, written to a temporary file that only replaces the journal
+// of the last run once something was moved
+struct Journal{
+    location:PathBuf,
+    tmp_location:PathBuf,
+    file:File,
+    is_renamed:bool,
+}
+impl Journal{
+    fn create() -> Result<Journal, anyhow::Error>{
+        let location = journal_location().ok_or_else(|| anyhow!("no data directory for the journal"))?;
+        if let Some(dir) = location.parent(){
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_location = location.with_extension("tmp");
+        let mut file = File::create(&tmp_location)?;
+        bincode::serialize_into(&mut file, &JOURNAL_VERSION)?;
+        Ok(Journal{location,tmp_location,file,is_renamed:false})
+    }
+    // each move is journaled right after it happened, the open file keeps its data when it is renamed
+    fn add(&mut self, planned:&Move) -> Result<(), anyhow::Error>{
+        let entry = JournalEntry{from:planned.from.clone().into(),to:planned.to.clone().into(),root:planned.root.clone().into()};
+        bincode::serialize_into(&mut self.file, &entry)?;
+        self.file.sync_data()?;
+        if !self.is_renamed {
+            fs::rename(&self.tmp_location, &self.location)?;
+            self.is_renamed = true;
+        }
+        Ok(())
+    }
+    fn finish(self) -> Result<(), anyhow::Error>{
+        match self.is_renamed{
+            true => self.file.sync_all()?,
+            false => fs::remove_file(&self.tmp_location)?,
+        }
+        Ok(())
+    }
+    // the moves of the last run, oldest first; a move cut off by a crash ends the list
+    fn read(location:&Path) -> Result<Vec<JournalEntry>, anyhow::Error>{
+        let file = File::open(location).map_err(|_| anyhow!("there is nothing to undo"))?;
+        let mut reader = BufReader::new(file);
+        let version:u32 = bincode::deserialize_from(&mut reader).map_err(|_| anyhow!("the journal is empty"))?;
+        if version != JOURNAL_VERSION {
+            return Err(anyhow!("the journal was written by another version of rplayer"))
+        }
+        let mut entries = Vec::new();
+        while let Ok(entry) = bincode::deserialize_from(&mut reader){
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+    // keeps the moves that could not be undone, through a temporary file like the journal itself
+    fn write(location:&Path, entries:&[JournalEntry]) -> Result<(), anyhow::Error>{
+        let tmp_location = location.with_extension("tmp");
+        let mut file = File::create(&tmp_location)?;
+        bincode::serialize_into(&mut file, &JOURNAL_VERSION)?;
+        for entry in entries{
+            bincode::serialize_into(&mut file, entry)?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_location, location)?;
+        Ok(())
+    }
+}
+
+// moves the files without a conflict, the journal of the last run is kept when nothing moves
+pub fn apply(moves:&[Move]) -> Result<Outcome, anyhow::Error>{
+    let mut journal = Journal::create()?;
+    let mut outcome = Outcome{moved:Vec::new(),errors:Vec::new()};
+    for planned in moves.iter().filter(|planned| planned.conflict.is_none()){
+        match move_file(&planned.from, &planned.to, &planned.root){
+            Ok(()) => {
+                // the file did move, it is only the undo that is lost
+                if let Err(e) = journal.add(planned) {
+                    outcome.errors.push(format!("{}: moved, but cannot be undone: {}", planned.to.display(), e));
+                }
+                outcome.moved.push((planned.from.clone(), planned.to.clone()));
+            }
+            Err(e) => outcome.errors.push(format!("{}: {}", planned.from.display(), e)),
+        }
+    }
+    if let Err(e) = journal.finish() {
+        outcome.errors.push(format!("cannot finish the journal: {}", e));
+    }
+    Ok(outcome)
+}
+
+// moves the files of the last run back, the journal is kept with whatever could not be undone
+pub fn undo() -> Result<Outcome, anyhow::Error>{
+    let location = journal_location().ok_or_else(|| anyhow!("no data directory for the journal"))?;
+    let entries = Journal::read(&location)?;
+    let mut outcome = Outcome{moved:Vec::new(),errors:Vec::new()};
+    let mut remaining = Vec::new();
+    for entry in entries.into_iter().rev(){
+        let (from, to) = (PathBuf::from(&entry.from), PathBuf::from(&entry.to));
+        match move_file(&to, &from, Path::new(&entry.root)){
+            Ok(()) => outcome.moved.push((to, from)),
+            Err(e) => {
+                outcome.errors.push(format!("{}: {}", to.display(), e));
+                remaining.insert(0, entry);
+            }
+        }
+    }
+    if remaining.is_empty() { fs::remove_file(&location)? } else { Journal::write(&location, &remaining)? }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn info() -> TrackInfo{
+        TrackInfo{
+            title:String::from("What/If?"),
+            artist:String::from("Artist"),
+            album:String::from("Album"),
+            track_number:Some(3),
+            year:Some(1999),
+            ..TrackInfo::default()
+        }
+    }
+
+    #[test]
+    fn parse_errors(){
+        assert!(Pattern::parse(DEFAULT_PATTERN).is_ok());
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse("{artist").is_err());
+        assert!(Pattern::parse("{composer}").is_err());
+        assert!(Pattern::parse("{track:two}").is_err());
+    }
+
+    #[test]
+    fn render_the_default_pattern(){
+        let pattern = Pattern::parse(DEFAULT_PATTERN).unwrap();
+        let target = pattern.render(Path::new("/music/old name.mp3"), &info());
+        assert_eq!(target, Path::new("Artist/Album/03 - What_If_.mp3"));
+    }
+
+    #[test]
+    fn missing_tags(){
+        let pattern = Pattern::parse("{album-artist}/{year} {album}/{disc}{track:02} - {title}").unwrap();
+        let target = pattern.render(Path::new("/music/old name.MP3"), &TrackInfo::default());
+        // placeholders for text, numbers and the separators after them are left out, the extension is kept
+        assert_eq!(target, Path::new("Unknown artist/Unknown album/old name.MP3"));
+    }
+
+    #[test]
+    fn tags_never_add_folders(){
+        let pattern = Pattern::parse("{artist}/{filename}").unwrap();
+        let info = TrackInfo{artist:String::from("../.."),..TrackInfo::default()};
+        assert_eq!(pattern.render(Path::new("/music/song.mp3"), &info), Path::new(".._/song.mp3"));
+        let info = TrackInfo{artist:String::from(".."),..TrackInfo::default()};
+        assert_eq!(pattern.render(Path::new("/music/song.mp3"), &info), Path::new("_/song.mp3"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn journal_keeps_any_file_name(){
+        use std::os::unix::ffi::OsStrExt;
+        let location = std::env::temp_dir().join(format!("rplayer-journal-test-{}", std::process::id()));
+        let latin1 = std::ffi::OsStr::from_bytes(b"/music/caf\xe9.mp3");
+        let entries = vec![
+            JournalEntry{from:latin1.to_os_string(),to:OsString::from("/music/Artist/cafe.mp3"),root:OsString::from("/music")},
+            JournalEntry{from:OsString::from("/music/tab\tand\nnewline.mp3"),to:OsString::from("/music/Artist/x.mp3"),root:OsString::from("/music")},
+        ];
+        Journal::write(&location, &entries).unwrap();
+        let read = Journal::read(&location).unwrap();
+        fs::remove_file(&location).unwrap();
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].from, latin1);
+        assert_eq!(read[1].from, "/music/tab\tand\nnewline.mp3");
+        assert_eq!(read[1].to, "/music/Artist/x.mp3");
+        assert_eq!(read[1].root, "/music");
+    }
+
+    #[test]
+    fn emptied_folders_are_removed_up_to_the_root(){
+        let dir = std::env::temp_dir().join(format!("rplayer-organise-test-{}", std::process::id()));
+        let root = dir.join("music");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/song.mp3"), b"").unwrap();
+        move_file(&root.join("a/b/song.mp3"), &dir.join("elsewhere/song.mp3"), &root).unwrap();
+        assert!(!root.join("a").exists());
+        // the scanned directory stays even when nothing is left in it
+        assert!(root.is_dir());
+        assert!(dir.join("elsewhere/song.mp3").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn journal_of_another_version(){
+        let location = std::env::temp_dir().join(format!("rplayer-journal-test-text-{}", std::process::id()));
+        fs::write(&location, "/music/a.mp3\t/music/b.mp3\n").unwrap();
+        let read = Journal::read(&location);
+        fs::remove_file(&location).unwrap();
+        assert!(read.is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use std::path::{Path, PathBuf};
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::file_manager::FileManager;
+use crate::organise::{self, Move, Pattern};
+
+// the pattern prompt with a preview of the moves, updated while the pattern is typed
+pub struct OrganiseView{
+    paths:Vec<PathBuf>,
+    pattern:String,
+    moves:Vec<Move>,
+    error:Option<String>,
+    viewport_size:usize,
+    start_index:Cell<usize>,
+}
+impl OrganiseView{
+    pub fn new(paths:Vec<PathBuf>, pattern:String, viewport_size:usize, file_manager:&mut FileManager) -> OrganiseView{
+        let mut organise_view = OrganiseView{paths,pattern,moves:Vec::new(),error:None,viewport_size,start_index:Cell::new(0)};
+        organise_view.update(file_manager);
+        organise_view
+    }
+    pub fn pattern(&self) -> &str{
+        &self.pattern
+    }
+    pub fn moves(&self) -> &[Move]{
+        &self.moves
+    }
+    // the moves are from an older pattern while the typed one does not parse
+    pub fn error(&self) -> Option<&str>{
+        self.error.as_deref()
+    }
+    pub fn input(&mut self, c:char, file_manager:&mut FileManager){
+        self.pattern.push(c);
+        self.update(file_manager);
+    }
+    pub fn backspace(&mut self, file_manager:&mut FileManager){
+        self.pattern.pop();
+        self.update(file_manager);
+    }
+    fn update(&mut self, file_manager:&mut FileManager){
+        match Pattern::parse(&self.pattern){
+            Ok(pattern) => {
+                self.moves = organise::plan(file_manager, &self.paths, &pattern);
+                self.error = None;
+            }
+            // the last valid preview stays while the pattern is incomplete
+            Err(e) => self.error = Some(e.to_string()),
+        }
+        self.scroll_by(0);
+    }
+    // the prompt, the summary and the help take three rows of the list area
+    pub fn page_size(&self) -> usize{
+        self.viewport_size.saturating_sub(3)
+    }
+    pub fn scroll_by(&self, n:i32){
+        let max_start = self.moves.len().saturating_sub(self.page_size());
+        self.start_index.set(((self.start_index.get() as i32 + n).max(0) as usize).min(max_start));
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let conflicts = self.moves.iter().filter(|planned| planned.conflict.is_some()).count();
+        let title = format!("Organise {} tracks: {} to move, {} conflicts", self.paths.len(), self.moves.len()-conflicts, conflicts);
+        graphics::draw_text(stdout,style(graphics::set_text_width(title, row_width)).on_dark_blue(),x,y).unwrap();
+        y+=1;
+        let prompt = graphics::set_text_width(format!("Pattern: {}_", self.pattern), row_width);
+        graphics::draw_text(stdout,style(prompt).yellow(),x,y).unwrap();
+        y+=1;
+        if let Some(error) = self.error.as_ref(){
+            graphics::draw_text(stdout,style(graphics::set_text_width(error.clone(), row_width)).red(),x,y).unwrap();
+        }
+        else if self.moves.is_empty(){
+            graphics::draw_text(stdout,style(String::from("Every track is already in place")).dark_grey(),x,y).unwrap();
+        }
+        y+=1;
+        let relative = |path:&Path, root:&Path| path.strip_prefix(root).unwrap_or(path).display().to_string();
+        let start_index = self.start_index.get();
+        for (index,planned) in self.moves.iter().enumerate().skip(start_index).take(self.page_size()){
+            let mut text = format!("{} → {}", relative(&planned.from, &planned.root), relative(&planned.to, &planned.root));
+            if let Some(conflict) = planned.conflict.as_ref(){
+                text = format!("{} ({})", text, conflict);
+            }
+            let mut description = style(graphics::set_text_width(text, row_width));
+            if planned.conflict.is_some(){
+                description = description.red();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+        let help = style(String::from("Enter moves the tracks without conflicts, Esc cancels, Shift + U undoes the last run")).dark_grey();
+        graphics::draw_text(stdout,help,x,y+self.page_size() as u16).unwrap();
+    }
+}
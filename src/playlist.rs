@@ -0,0 +1,413 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use anyhow::anyhow;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+
+#[derive(Default)]
+pub struct PlaylistEntry{
+    pub path:PathBuf,
+    pub title:Option<String>,
+    pub artist:Option<String>,
+    pub album:Option<String>,
+    pub duration:Option<Duration>,
+}
+impl PlaylistEntry{
+    pub fn new(path:PathBuf) -> PlaylistEntry{
+        PlaylistEntry{path,..PlaylistEntry::default()}
+    }
+    // m3u and pls only have one name field, usually "<artist> - <title>"
+    fn set_name(&mut self, name:&str){
+        match name.split_once(" - "){
+            Some((artist, title)) => {
+                self.artist = Some(artist.to_string());
+                self.title = Some(title.to_string());
+            }
+            None if !name.is_empty() => self.title = Some(name.to_string()),
+            None => (),
+        }
+    }
+    fn name(&self) -> Option<String>{
+        match (self.artist.as_ref(), self.title.as_ref()){
+            (Some(artist), Some(title)) => Some(format!("{} - {}", artist, title)),
+            (_, title) => title.cloned(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format{
+    M3u,
+    M3u8,
+    Pls,
+    Xspf,
+}
+impl Format{
+    fn of(path:&Path) -> Option<Format>{
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str(){
+            "m3u" => Some(Format::M3u),
+            "m3u8" => Some(Format::M3u8),
+            "pls" => Some(Format::Pls),
+            "xspf" => Some(Format::Xspf),
+            _ => None,
+        }
+    }
+}
+
+pub fn is_playlist(path:&Path) -> bool{
+    path.is_file() && Format::of(path).is_some()
+}
+
+fn decode_uri(uri:&str) -> String{
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i<bytes.len(){
+        let escaped = uri.get(i+1..i+3).filter(|_| bytes[i] == b'%').and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped{
+            Some(byte) => { decoded.push(byte); i+=3; }
+            None => { decoded.push(bytes[i]); i+=1; }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+fn encode_uri(path:&str) -> String{
+    path.bytes().map(|byte| match byte{
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte),
+    }).collect()
+}
+
+// entries are resolved against the directory of the playlist file
+fn resolve(base:&Path, location:&str) -> PathBuf{
+    match location.strip_prefix("file://"){
+        Some(path) => PathBuf::from(decode_uri(path.strip_prefix("localhost").unwrap_or(path))),
+        None => base.join(location),
+    }
+}
+// xspf locations are URIs, relative ones are escaped as well and are only decoded once
+fn resolve_uri(base:&Path, location:&str) -> PathBuf{
+    match location.starts_with("file://"){
+        true => resolve(base, location),
+        false => base.join(decode_uri(location)),
+    }
+}
+// the opposite of resolve, files below the playlist directory are written relative to it
+fn relative_location(base:&Path, path:&Path) -> String{
+    path.strip_prefix(base).unwrap_or(path).to_string_lossy().into_owned()
+}
+
+// m3u and pls files are often latin-1, .m3u8 and xspf files are always utf-8
+fn decode(bytes:Vec<u8>, format:Format) -> String{
+    match String::from_utf8(bytes){
+        Ok(text) => text,
+        Err(e) if format == Format::M3u || format == Format::Pls => e.into_bytes().iter().map(|byte| *byte as char).collect(),
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+fn parse_m3u(text:&str, base:&Path) -> Vec<PlaylistEntry>{
+    let mut entries = Vec::new();
+    let mut info:Option<(Option<Duration>, String)> = None;
+    for line in text.lines().map(|line| line.trim_start_matches('\u{feff}').trim()){
+        if let Some(extinf) = line.strip_prefix("#EXTINF:"){
+            // #EXTINF:<seconds>,<artist> - <title>, -1 seconds means unknown
+            let (seconds, name) = extinf.split_at(extinf.find(',').unwrap_or(extinf.len()));
+            let duration = seconds.trim().parse::<f64>().ok().filter(|seconds| *seconds>=0.0).map(Duration::from_secs_f64);
+            info = Some((duration, name.trim_start_matches(',').trim().to_string()));
+        }
+        else if !line.is_empty() && !line.starts_with('#'){
+            let mut entry = PlaylistEntry::new(resolve(base, line));
+            if let Some((duration, name)) = info.take(){
+                entry.duration = duration;
+                entry.set_name(&name);
+            }
+            entries.push(entry);
+        }
+    }
+    entries
+}
+
+fn write_m3u(entries:&[PlaylistEntry], base:&Path) -> String{
+    let mut text = String::from("#EXTM3U\n");
+    for entry in entries{
+        if entry.title.is_some() || entry.duration.is_some(){
+            let seconds = entry.duration.map_or(-1, |duration| duration.as_secs() as i64);
+            text += &format!("#EXTINF:{},{}\n", seconds, entry.name().unwrap_or_default());
+        }
+        text += &relative_location(base, &entry.path);
+        text.push('\n');
+    }
+    text
+}
+
+// [playlist] with File<n>, Title<n> and Length<n> keys, entries without a file are counted as invalid
+fn parse_pls(text:&str, base:&Path) -> (Vec<PlaylistEntry>, usize){
+    let mut entries:BTreeMap<u32, (Option<PathBuf>, PlaylistEntry)> = BTreeMap::new();
+    for line in text.lines().map(|line| line.trim()){
+        let (key, value) = match line.split_once('='){
+            Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+            None => continue,
+        };
+        let split = key.find(|c:char| c.is_ascii_digit()).unwrap_or(key.len());
+        let number = match key[split..].parse(){
+            Ok(number) => number,
+            Err(_) => continue,
+        };
+        let (path, entry) = entries.entry(number).or_default();
+        match &key[..split]{
+            "file" => *path = Some(resolve(base, value)),
+            "title" => entry.set_name(value),
+            "length" => entry.duration = value.parse::<f64>().ok().filter(|seconds| *seconds>=0.0).map(Duration::from_secs_f64),
+            _ => (),
+        }
+    }
+    let invalid = entries.values().filter(|(path, _)| path.is_none()).count();
+    let entries = entries.into_values().filter_map(|(path, entry)| Some(PlaylistEntry{path:path?, ..entry})).collect();
+    (entries, invalid)
+}
+
+fn write_pls(entries:&[PlaylistEntry], base:&Path) -> String{
+    let mut text = String::from("[playlist]\n");
+    for (number, entry) in entries.iter().enumerate().map(|(index, entry)| (index+1, entry)){
+        text += &format!("File{}={}\n", number, relative_location(base, &entry.path));
+        if let Some(name) = entry.name(){
+            text += &format!("Title{}={}\n", number, name);
+        }
+        text += &format!("Length{}={}\n", number, entry.duration.map_or(-1, |duration| duration.as_secs() as i64));
+    }
+    text += &format!("NumberOfEntries={}\nVersion=2\n", entries.len());
+    text
+}
+
+// only the fields of <track> that rplayer shows are read, tracks without a location are counted as invalid
+fn parse_xspf(text:&str, base:&Path) -> Result<(Vec<PlaylistEntry>, usize), anyhow::Error>{
+    let mut reader = Reader::from_str(text);
+    reader.trim_text(true);
+    let mut entries = Vec::new();
+    let mut invalid = 0;
+    let mut track:Option<(Option<PathBuf>, PlaylistEntry)> = None;
+    let mut element = String::new();
+    loop{
+        match reader.read_event()?{
+            Event::Start(start) => {
+                element = String::from_utf8_lossy(start.local_name().as_ref()).into_owned();
+                if element == "track" { track = Some((None, PlaylistEntry::default())) }
+            }
+            Event::End(end) => {
+                if end.local_name().as_ref() == b"track"{
+                    match track.take(){
+                        Some((Some(path), entry)) => entries.push(PlaylistEntry{path, ..entry}),
+                        Some((None, _)) => invalid+=1,
+                        None => (),
+                    }
+                }
+                element.clear();
+            }
+            Event::Text(text) => {
+                if let Some((path, entry)) = track.as_mut(){
+                    let value = text.unescape()?.into_owned();
+                    match element.as_str(){
+                        // a track can list several locations, the first one is used
+                        "location" if path.is_none() => *path = Some(resolve_uri(base, &value)),
+                        "title" => entry.title = Some(value),
+                        "creator" => entry.artist = Some(value),
+                        "album" => entry.album = Some(value),
+                        "duration" => entry.duration = value.parse().ok().map(Duration::from_millis),
+                        _ => (),
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+    }
+    Ok((entries, invalid))
+}
+
+fn escape_xml(text:&str) -> String{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+fn write_xspf(entries:&[PlaylistEntry], base:&Path) -> String{
+    let mut text = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <trackList>\n");
+    for entry in entries{
+        // relative uris for files below the playlist, file:// uris for everything else
+        let location = match entry.path.strip_prefix(base){
+            Ok(relative) => encode_uri(&relative.to_string_lossy()),
+            Err(_) => format!("file://{}", encode_uri(&fs::canonicalize(&entry.path).unwrap_or_else(|_| entry.path.clone()).to_string_lossy())),
+        };
+        text += "    <track>\n";
+        text += &format!("      <location>{}</location>\n", escape_xml(&location));
+        if let Some(title) = entry.title.as_ref(){
+            text += &format!("      <title>{}</title>\n", escape_xml(title));
+        }
+        if let Some(artist) = entry.artist.as_ref(){
+            text += &format!("      <creator>{}</creator>\n", escape_xml(artist));
+        }
+        if let Some(album) = entry.album.as_ref(){
+            text += &format!("      <album>{}</album>\n", escape_xml(album));
+        }
+        if let Some(duration) = entry.duration{
+            text += &format!("      <duration>{}</duration>\n", duration.as_millis());
+        }
+        text += "    </track>\n";
+    }
+    text += "  </trackList>\n</playlist>\n";
+    text
+}
+
+pub struct Playlist{
+    pub entries:Vec<PlaylistEntry>,
+    // entries that could not be turned into a path at all
+    pub invalid:usize,
+}
+impl Playlist{
+    pub fn new(entries:Vec<PlaylistEntry>) -> Playlist{
+        Playlist{entries,invalid:0}
+    }
+    pub fn load(path:&Path) -> Result<Playlist, anyhow::Error>{
+        let format = Format::of(path).ok_or_else(|| anyhow!("{} is not a playlist", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let text = decode(fs::read(path)?, format);
+        let (entries, invalid) = match format{
+            Format::M3u | Format::M3u8 => (parse_m3u(&text, base), 0),
+            Format::Pls => parse_pls(&text, base),
+            Format::Xspf => parse_xspf(&text, base)?,
+        };
+        Ok(Playlist{entries,invalid})
+    }
+    // the format follows the extension, text is always written as utf-8
+    pub fn save(&self, path:&Path) -> Result<(), anyhow::Error>{
+        let format = Format::of(path).ok_or_else(|| anyhow!("{} is not a playlist", path.display()))?;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        let text = match format{
+            Format::M3u | Format::M3u8 => write_m3u(&self.entries, base),
+            Format::Pls => write_pls(&self.entries, base),
+            Format::Xspf => write_xspf(&self.entries, base),
+        };
+        fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn entry(path:&str, name:&str, seconds:u64) -> PlaylistEntry{
+        let mut entry = PlaylistEntry::new(PathBuf::from(path));
+        entry.set_name(name);
+        entry.duration = Some(Duration::from_secs(seconds));
+        entry
+    }
+
+    #[test]
+    fn m3u_round_trip(){
+        let base = Path::new("/music/mix");
+        let entries = vec![
+            entry("/music/mix/side a/one.mp3", "Artist - One", 215),
+            entry("/elsewhere/two.mp3", "Two", 90),
+            PlaylistEntry::new(PathBuf::from("/music/mix/three.mp3")),
+        ];
+        let text = write_m3u(&entries, base);
+        // files below the playlist are written relative to it
+        assert!(text.contains("\nside a/one.mp3\n"));
+        assert!(text.contains("\n/elsewhere/two.mp3\n"));
+        let parsed = parse_m3u(&text, base);
+        assert_eq!(parsed.len(), 3);
+        for (parsed, entry) in parsed.iter().zip(entries.iter()){
+            assert_eq!(parsed.path, entry.path);
+            assert_eq!(parsed.title, entry.title);
+            assert_eq!(parsed.artist, entry.artist);
+            assert_eq!(parsed.duration, entry.duration);
+        }
+    }
+
+    #[test]
+    fn extinf_belongs_to_the_next_file_only(){
+        let text = "\u{feff}#EXTM3U\r\n#EXTINF:-1,Artist - Title\r\n\r\n# a comment\r\nrelative/one.mp3\r\ntwo.mp3\r\n#EXTINF:12.5\r\nfile:///abs/three%20x.mp3\r\n";
+        let parsed = parse_m3u(text, Path::new("/base"));
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].path, PathBuf::from("/base/relative/one.mp3"));
+        assert_eq!(parsed[0].artist.as_deref(), Some("Artist"));
+        assert_eq!(parsed[0].title.as_deref(), Some("Title"));
+        // -1 seconds means unknown
+        assert_eq!(parsed[0].duration, None);
+        assert_eq!(parsed[1].path, PathBuf::from("/base/two.mp3"));
+        assert_eq!(parsed[1].title, None);
+        assert_eq!(parsed[2].path, PathBuf::from("/abs/three x.mp3"));
+        assert_eq!(parsed[2].title, None);
+        assert_eq!(parsed[2].duration, Some(Duration::from_secs_f64(12.5)));
+    }
+
+    #[test]
+    fn latin1_only_for_m3u(){
+        let bytes = vec![b'c', 0xe9, b'.', b'm', b'p', b'3'];
+        assert_eq!(decode(bytes.clone(), Format::M3u), "cé.mp3");
+        assert_eq!(decode(bytes, Format::M3u8), "c\u{fffd}.mp3");
+    }
+
+    #[test]
+    fn pls_round_trip(){
+        let base = Path::new("/music/mix");
+        let entries = vec![
+            entry("/music/mix/one.mp3", "Artist - One", 215),
+            entry("/elsewhere/two.mp3", "Two", 90),
+        ];
+        let (parsed, invalid) = parse_pls(&write_pls(&entries, base), base);
+        assert_eq!(invalid, 0);
+        assert_eq!(parsed.len(), 2);
+        for (parsed, entry) in parsed.iter().zip(entries.iter()){
+            assert_eq!(parsed.path, entry.path);
+            assert_eq!(parsed.title, entry.title);
+            assert_eq!(parsed.artist, entry.artist);
+            assert_eq!(parsed.duration, entry.duration);
+        }
+    }
+
+    #[test]
+    fn pls_entries_without_a_file_are_invalid(){
+        let text = "[playlist]\nfile2=b.mp3\nTitle1=No file\nFile10=c.mp3\nLength10=-1\nNumberOfEntries=3\n";
+        let (parsed, invalid) = parse_pls(text, Path::new("/base"));
+        assert_eq!(invalid, 1);
+        // entries keep their numbered order, not the order of the lines
+        assert_eq!(parsed.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>(), vec![PathBuf::from("/base/b.mp3"), PathBuf::from("/base/c.mp3")]);
+        assert_eq!(parsed[1].duration, None);
+    }
+
+    #[test]
+    fn xspf_round_trip(){
+        let base = Path::new("/music/mix");
+        let mut first = entry("/music/mix/a b/100% & more.mp3", "Artist - One", 215);
+        first.album = Some("<Album>".to_string());
+        let entries = vec![first, PlaylistEntry::new(PathBuf::from("/nonexistent-rplayer/tw o.mp3"))];
+        let text = write_xspf(&entries, base);
+        assert!(text.contains("<location>a%20b/100%25%20%26%20more.mp3</location>"));
+        assert!(text.contains("<location>file:///nonexistent-rplayer/tw%20o.mp3</location>"));
+        let (parsed, invalid) = parse_xspf(&text, base).unwrap();
+        assert_eq!(invalid, 0);
+        assert_eq!(parsed.len(), 2);
+        for (parsed, entry) in parsed.iter().zip(entries.iter()){
+            assert_eq!(parsed.path, entry.path);
+            assert_eq!(parsed.title, entry.title);
+            assert_eq!(parsed.artist, entry.artist);
+            assert_eq!(parsed.album, entry.album);
+            assert_eq!(parsed.duration, entry.duration);
+        }
+    }
+
+    #[test]
+    fn xspf_locations_are_decoded_once(){
+        let text = "<playlist><trackList>\
+            <track><location>a%2525.mp3</location><location>ignored.mp3</location></track>\
+            <track><location>file://localhost/abs/b%2525.mp3</location></track>\
+            <track><title>no location</title></track>\
+            </trackList></playlist>";
+        let (parsed, invalid) = parse_xspf(text, Path::new("/base")).unwrap();
+        assert_eq!(invalid, 1);
+        assert_eq!(parsed[0].path, PathBuf::from("/base/a%25.mp3"));
+        assert_eq!(parsed[1].path, PathBuf::from("/abs/b%25.mp3"));
+    }
+}
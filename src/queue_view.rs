@@ -0,0 +1,77 @@
+use std::cell::Cell;
+use std::io::Stdout;
+use crossterm::style::{style, Colorize};
+use crate::{graphics, relative_size};
+use crate::file_manager::FileManager;
+
+// the tracks queued with "play next" and "add to queue", in the order they will play
+pub struct QueueView{
+    viewport_size:usize,
+    selected_index:Cell<usize>,
+    start_index:Cell<usize>,
+}
+impl QueueView{
+    pub fn new(viewport_size:usize) -> QueueView{
+        QueueView{viewport_size,selected_index:Cell::new(0),start_index:Cell::new(0)}
+    }
+    pub fn page_size(&self) -> usize{
+        self.viewport_size
+    }
+    pub fn selected(&self) -> usize{
+        self.selected_index.get()
+    }
+    // keeps the selection inside the queue and on screen, the queue shrinks while tracks play
+    pub fn select(&self, index:usize, len:usize){
+        let index = index.min(len.saturating_sub(1));
+        self.selected_index.set(index);
+        if index<self.start_index.get(){
+            self.start_index.set(index);
+        }
+        else if index>=self.start_index.get()+self.viewport_size{
+            self.start_index.set(index+1-self.viewport_size);
+        }
+    }
+    pub fn move_selection_by(&self, n:i32, len:usize){
+        let index = (self.selected_index.get() as i32 + n).max(0) as usize;
+        self.select(index, len);
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, file_manager:&mut FileManager, x:u16, mut y:u16, width:u16){
+        let queued = file_manager.queued();
+        self.select(self.selected_index.get(), queued.len());
+        let padding = relative_size(1.0/4.0,width) as usize;
+        let header = style(format!("{} {} {} {}",
+                                   graphics::set_text_width(format!("Queue ({})", queued.len()),padding),
+                                   graphics::set_text_width(String::from("Track"),padding),
+                                   graphics::set_text_width(String::from("Artist"),padding),
+                                   graphics::set_text_width(String::from("Duration"),padding)
+        )).on_dark_blue();
+        graphics::draw_text(stdout,header,x,y).unwrap();
+        y+=1;
+        if queued.is_empty(){
+            let empty = style(String::from("The queue is empty, tracks play in directory order")).dark_grey();
+            graphics::draw_text(stdout,empty,x,y).unwrap();
+            return
+        }
+        let start_index = self.start_index.get();
+        for (index,path) in queued.iter().enumerate().skip(start_index).take(self.viewport_size){
+            let filename = graphics::set_text_width(String::from(path.file_name().unwrap().to_str().unwrap_or("ERROR READING!")),padding-5);
+            let info = file_manager.get_metadata(path).unwrap_or_default();
+            let display_index = graphics::set_text_width(format!("{}.",index+1),5);
+            let mut description = style(format!("{}{} {} {} {}",
+                                                display_index,
+                                                filename,
+                                                graphics::set_text_width(info.title, padding),
+                                                graphics::set_text_width(info.artist, padding),
+                                                graphics::set_text_width(graphics::duration_to_mmss(info.duration), padding)
+            ));
+            if file_manager.is_bad(path){
+                description = description.red();
+            }
+            if index == self.selected_index.get(){
+                description = description.on_blue();
+            }
+            graphics::draw_text(stdout,description,x,y+(index-start_index) as u16).unwrap();
+        }
+    }
+}
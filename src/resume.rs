@@ -0,0 +1,67 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+use anyhow::anyhow;
+use crate::file_manager::FileManager;
+
+// bump when ResumeState changes, older states are then ignored
+const STATE_VERSION:u32 = 1;
+const STATE_FILE:&str = "resume_state";
+
+// the XDG state dir, systems without one keep it with the library index
+pub fn location() -> Option<PathBuf>{
+    dirs::state_dir().or_else(dirs::data_dir).map(|dir| dir.join("rplayer").join(STATE_FILE))
+}
+
+// paths are compared the way they were given, relative ones only match from the same directory
+fn absolute(paths:&[PathBuf]) -> Vec<PathBuf>{
+    paths.iter().map(|path| path.canonicalize().unwrap_or_else(|_| path.clone())).collect()
+}
+
+// where the last session stopped
+#[derive(Serialize, Deserialize)]
+pub struct ResumeState{
+    version:u32,
+    // the scanned directories or the playlist
+    pub paths:Vec<PathBuf>,
+    pub track:PathBuf,
+    // the play order while shuffled, empty otherwise
+    pub order:Vec<PathBuf>,
+    pub timestamp:Duration,
+    pub volume:f32,
+}
+impl ResumeState{
+    // `track` is the one that plays, a queued track is not at the position in the list
+    pub fn new(paths:&[PathBuf], file_manager:&FileManager, track:PathBuf, timestamp:Duration, volume:f32) -> ResumeState{
+        let order = match file_manager.is_shuffled(){
+            true => file_manager.get_paths(0, file_manager.file_paths.len()),
+            false => Vec::new(),
+        };
+        ResumeState{version:STATE_VERSION,paths:absolute(paths),track,order,timestamp,volume}
+    }
+    // a missing, broken or outdated state is no state
+    pub fn load() -> Option<ResumeState>{
+        let file = File::open(location()?).ok()?;
+        bincode::deserialize_from::<_, ResumeState>(BufReader::new(file)).ok()
+            .filter(|state| state.version == STATE_VERSION)
+    }
+    pub fn save(&self) -> Result<(), anyhow::Error>{
+        let location = location().ok_or_else(|| anyhow!("no state directory for the resume state"))?;
+        if let Some(dir) = location.parent(){
+            fs::create_dir_all(dir)?;
+        }
+        // a temporary file first, the player can be killed while it writes
+        let tmp_location = location.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_location)?);
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&tmp_location, location)?;
+        Ok(())
+    }
+    // the state only applies to the same directories or playlist
+    pub fn is_for(&self, paths:&[PathBuf]) -> bool{
+        self.paths == absolute(paths)
+    }
+}
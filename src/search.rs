@@ -0,0 +1,51 @@
+use crate::file_manager::FileManager;
+
+// case-insensitive subsequence match, spaces in the query are ignored
+// returns the char indices of the matched characters in `text`
+pub fn fuzzy_match(query:&str, text:&str) -> Option<Vec<usize>>{
+    let mut wanted = query.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).peekable();
+    wanted.peek()?;
+    let mut indices = Vec::new();
+    for (index, c) in text.chars().enumerate(){
+        match wanted.peek(){
+            Some(next) if c.to_lowercase().eq(std::iter::once(*next)) => {
+                indices.push(index);
+                wanted.next();
+            }
+            Some(_) => (),
+            None => break,
+        }
+    }
+    if wanted.peek().is_none() { Some(indices) } else { None }
+}
+
+pub struct Search{
+    pub query:String,
+    // the query is still being typed, the explorer only shows the matches meanwhile
+    pub is_typing:bool,
+    // where the cursor was before the search, restored when it is cancelled
+    pub origin:Option<usize>,
+}
+impl Search{
+    pub fn new(origin:Option<usize>) -> Search{
+        Search{query:String::new(),is_typing:true,origin}
+    }
+    fn is_match(&self, file_manager:&mut FileManager, position:usize) -> bool{
+        let path = file_manager.path_at(position);
+        let info = file_manager.get_metadata(&path).unwrap_or_default();
+        let filename = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        [filename.as_str(), info.title.as_str(), info.artist.as_str(), info.album.as_str()].iter()
+            .any(|text| fuzzy_match(&self.query, text).is_some())
+    }
+    // positions in the current order where the filename, title, artist or album match
+    pub fn matches(&self, file_manager:&mut FileManager) -> Vec<usize>{
+        (0..file_manager.file_paths.len()).filter(|position| self.is_match(file_manager, *position)).collect()
+    }
+    // the closest match after (or before) `from`, wrapping around the list
+    pub fn next_match(&self, file_manager:&mut FileManager, from:usize, forward:bool) -> Option<usize>{
+        let len = file_manager.file_paths.len();
+        (1..=len)
+            .map(|offset| if forward { (from+offset)%len } else { (from+len-offset)%len })
+            .find(|position| self.is_match(file_manager, *position))
+    }
+}
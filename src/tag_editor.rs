@@ -0,0 +1,155 @@
+use std::io::Stdout;
+use std::path::PathBuf;
+use crossterm::style::{style, Colorize};
+use anyhow::anyhow;
+use crate::{graphics, relative_size};
+use crate::file_manager::FileManager;
+use crate::library::TrackInfo;
+
+#[derive(Clone, Copy, PartialEq)]
+enum TagField{
+    Title,
+    Artist,
+    Album,
+    AlbumArtist,
+    TrackNumber,
+    Year,
+    Genre,
+}
+impl TagField{
+    const ALL:[TagField; 7] = [TagField::Title, TagField::Artist, TagField::Album, TagField::AlbumArtist, TagField::TrackNumber, TagField::Year, TagField::Genre];
+    fn label(self) -> &'static str{
+        match self{
+            TagField::Title => "Title",
+            TagField::Artist => "Artist",
+            TagField::Album => "Album",
+            TagField::AlbumArtist => "Album artist",
+            TagField::TrackNumber => "Track",
+            TagField::Year => "Year",
+            TagField::Genre => "Genre",
+        }
+    }
+    fn frame_id(self) -> &'static str{
+        match self{
+            TagField::Title => "TIT2",
+            TagField::Artist => "TPE1",
+            TagField::Album => "TALB",
+            TagField::AlbumArtist => "TPE2",
+            TagField::TrackNumber => "TRCK",
+            TagField::Year => "TDRC",
+            TagField::Genre => "TCON",
+        }
+    }
+    fn value(self, info:&TrackInfo) -> String{
+        let number = |number:Option<u32>| number.map(|number| number.to_string()).unwrap_or_default();
+        match self{
+            TagField::Title => info.title.clone(),
+            TagField::Artist => info.artist.clone(),
+            TagField::Album => info.album.clone(),
+            TagField::AlbumArtist => info.album_artist.clone(),
+            TagField::TrackNumber => number(info.track_number),
+            TagField::Year => number(info.year),
+            TagField::Genre => info.genre.clone(),
+        }
+    }
+    // what the field accepts, empty always removes the tag
+    fn check(self, value:&str) -> Result<(), anyhow::Error>{
+        let is_number = |text:&str| !text.is_empty() && text.chars().all(|c| c.is_ascii_digit());
+        let is_valid = match self{
+            // "3" or "3/12"
+            TagField::TrackNumber => value.split('/').all(is_number) && value.split('/').count()<=2,
+            TagField::Year => is_number(value) && value.len() == 4,
+            _ => true,
+        };
+        if value.is_empty() || is_valid { Ok(()) } else { Err(anyhow!("{} is not a valid {}", value, self.label().to_lowercase())) }
+    }
+}
+
+struct FieldValue{
+    text:String,
+    // the tracks disagree, shown as such until it is typed over
+    is_mixed:bool,
+    is_changed:bool,
+}
+
+// a form for the tags of one track, or of several at once where only the changed fields are written
+pub struct TagEditor{
+    paths:Vec<PathBuf>,
+    fields:Vec<FieldValue>,
+    selected:usize,
+}
+impl TagEditor{
+    pub fn new(paths:Vec<PathBuf>, file_manager:&mut FileManager) -> TagEditor{
+        let infos:Vec<TrackInfo> = paths.iter().map(|path| file_manager.get_metadata(path).unwrap_or_default()).collect();
+        let fields = TagField::ALL.iter().map(|field| {
+            let mut values = infos.iter().map(|info| field.value(info));
+            let first = values.next().unwrap_or_default();
+            let is_mixed = values.any(|value| value != first);
+            FieldValue{text:if is_mixed { String::new() } else { first },is_mixed,is_changed:false}
+        }).collect();
+        TagEditor{paths,fields,selected:0}
+    }
+    pub fn paths(&self) -> &[PathBuf]{
+        &self.paths
+    }
+    // numbers only take digits, the track also its total after a slash
+    pub fn input(&mut self, c:char){
+        let is_accepted = match TagField::ALL[self.selected]{
+            TagField::TrackNumber => c.is_ascii_digit() || c == '/',
+            TagField::Year => c.is_ascii_digit() && self.fields[self.selected].text.len()<4,
+            _ => true,
+        };
+        if !is_accepted { return }
+        let field = &mut self.fields[self.selected];
+        field.text.push(c);
+        field.is_changed = true;
+    }
+    pub fn backspace(&mut self){
+        let field = &mut self.fields[self.selected];
+        field.text.pop();
+        field.is_changed = true;
+    }
+    pub fn move_field(&mut self, n:i32){
+        self.selected = (self.selected as i32 + n).rem_euclid(self.fields.len() as i32) as usize;
+    }
+    // frame ids and texts of the fields that were typed in, a cleared field removes the tag
+    pub fn edits(&self) -> Result<Vec<(&'static str, String)>, anyhow::Error>{
+        let mut edits = Vec::new();
+        for (field, value) in TagField::ALL.iter().zip(self.fields.iter()){
+            if !value.is_changed { continue }
+            let text = value.text.trim();
+            field.check(text)?;
+            edits.push((field.frame_id(), text.to_string()));
+        }
+        Ok(edits)
+    }
+
+    pub fn draw(&self, stdout:&mut Stdout, x:u16, mut y:u16, width:u16){
+        let row_width = relative_size(1.0/4.0,width) as usize*4+3;
+        let title = match self.paths.as_slice(){
+            [path] => format!("Edit tags of {}", path.file_name().unwrap_or_default().to_string_lossy()),
+            paths => format!("Edit tags of {} tracks", paths.len()),
+        };
+        graphics::draw_text(stdout,style(graphics::set_text_width(title, row_width)).on_dark_blue(),x,y).unwrap();
+        y+=1;
+        let label_width = 14;
+        for (index,(field, value)) in TagField::ALL.iter().zip(self.fields.iter()).enumerate(){
+            let is_selected = index == self.selected;
+            let label = graphics::set_text_width(format!("{}:", field.label()), label_width);
+            graphics::draw_text(stdout,style(label).dark_grey(),x,y).unwrap();
+            let mut text = if value.is_mixed && !value.is_changed {
+                style(graphics::set_text_width(String::from("(multiple values)"), row_width-label_width)).dark_grey()
+            } else {
+                let cursor = if is_selected { "_" } else { "" };
+                style(graphics::set_text_width(format!("{}{}", value.text, cursor), row_width-label_width))
+            };
+            if is_selected{
+                text = text.on_blue();
+            }
+            graphics::draw_text(stdout,text,x+label_width as u16,y).unwrap();
+            y+=1;
+        }
+        let help = style(String::from("Tab or Up/Down moves between fields, Enter saves, Esc cancels")).dark_grey();
+        graphics::draw_text(stdout,help,x,y+1).unwrap();
+    }
+}
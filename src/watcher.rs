@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+
+pub enum LibraryChange{
+    Added(PathBuf),
+    Removed(PathBuf),
+    Renamed(PathBuf, PathBuf),
+    Modified(PathBuf),
+}
+
+pub struct LibraryWatcher{
+    // dropping the watcher stops the notifications
+    _watcher:RecommendedWatcher,
+    receiver:mpsc::Receiver<notify::Result<Event>>,
+}
+impl LibraryWatcher{
+    pub fn new(roots:&[PathBuf]) -> notify::Result<LibraryWatcher>{
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for root in roots{
+            watcher.watch(root, RecursiveMode::Recursive)?;
+        }
+        Ok(LibraryWatcher{_watcher:watcher,receiver})
+    }
+    // changes received since the last call, never blocks
+    pub fn changes(&self) -> Vec<LibraryChange>{
+        let events:Vec<Event> = self.receiver.try_iter().filter_map(Result::ok).collect();
+        // inotify reports a rename as From, To and then Both with the same tracker,
+        // only the Both event is kept when all three arrived together
+        let renames:HashSet<usize> = events.iter()
+            .filter(|event| event.kind == EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .filter_map(|event| event.tracker())
+            .collect();
+        let mut changes = Vec::new();
+        for mut event in events{
+            let is_paired = event.tracker().is_some_and(|tracker| renames.contains(&tracker));
+            match event.kind{
+                EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                    let to = event.paths.pop().unwrap();
+                    let from = event.paths.pop().unwrap();
+                    changes.push(LibraryChange::Renamed(from, to));
+                }
+                EventKind::Modify(ModifyKind::Name(RenameMode::From)) if !is_paired => {
+                    changes.extend(event.paths.into_iter().map(LibraryChange::Removed));
+                }
+                EventKind::Modify(ModifyKind::Name(RenameMode::To)) if !is_paired => {
+                    changes.extend(event.paths.into_iter().map(LibraryChange::Added));
+                }
+                // other backends do not say which side of the rename a path is on
+                EventKind::Modify(ModifyKind::Name(RenameMode::Any)) => {
+                    changes.extend(event.paths.into_iter().map(|path| {
+                        if path.exists() { LibraryChange::Added(path) } else { LibraryChange::Removed(path) }
+                    }));
+                }
+                EventKind::Create(_) => changes.extend(event.paths.into_iter().map(LibraryChange::Added)),
+                EventKind::Remove(_) => changes.extend(event.paths.into_iter().map(LibraryChange::Removed)),
+                EventKind::Modify(ModifyKind::Data(_)) | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+                    changes.extend(event.paths.into_iter().map(LibraryChange::Modified));
+                }
+                _ => (),
+            }
+        }
+        changes
+    }
+}